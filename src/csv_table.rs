@@ -0,0 +1,234 @@
+use anyhow::{Context, Result, ensure};
+use markdown::mdast::{AlignKind, Code, Node, Table, TableCell, TableRow, Text};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::metadata::TableMetadata;
+
+/// Options of a fenced `csv` or `tsv` block, given as JSON after the language:
+///
+/// ```text
+/// ```csv {"file": "data/planets.csv", "caption": "The Planets", "ref": "planets"}
+/// ```
+///
+/// Without a `file`, the table data is the content of the block, which
+/// becomes a table only with `"table": true`. Blocks that import no table are
+/// shown as code.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvTableOptions {
+    pub file: Option<String>,
+    /// Turn the content of the block into a table
+    #[serde(default)]
+    pub table: bool,
+    pub caption: Option<String>,
+    pub r#ref: Option<String>,
+    pub widths: Option<Vec<f32>>,
+    pub autofit: Option<bool>,
+}
+
+impl CsvTableOptions {
+    /// Whether the block imports a table rather than showing its data as code
+    pub fn is_table(&self) -> bool {
+        self.file.is_some() || self.table
+    }
+
+    /// The caption, label and sizing of the table
+    pub fn table_metadata(&self) -> TableMetadata {
+        TableMetadata {
+            caption: self.caption.clone(),
+            r#ref: self.r#ref.clone(),
+            widths: self.widths.clone(),
+            autofit: self.autofit,
+        }
+    }
+}
+
+/// Returns the field delimiter if the code block is a `csv` or `tsv` table
+pub fn delimiter(code: &Code) -> Option<u8> {
+    match code.lang.as_deref()?.to_ascii_lowercase().as_str() {
+        "csv" => Some(b','),
+        "tsv" => Some(b'\t'),
+        _ => None,
+    }
+}
+
+/// Parses the options of a table block, or returns `None` if it has none
+pub fn options(code: &Code) -> Option<serde_json::Result<CsvTableOptions>> {
+    match code.meta.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(meta) => Some(serde_json::from_str(meta)),
+    }
+}
+
+/// Reads the table data, from `options.file` relative to `base_path` if set,
+/// and converts it into a Markdown table whose first row is the header.
+/// Fails if there is no data
+pub fn read_table(
+    code: &Code,
+    delimiter: u8,
+    options: &CsvTableOptions,
+    base_path: Option<&Path>,
+) -> Result<Table> {
+    let data = match &options.file {
+        Some(file) => {
+            let path = base_path.map_or_else(|| Path::new(file).to_path_buf(), |b| b.join(file));
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read table file {}", path.display()))?
+        }
+        None => code.value.clone(),
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(data.as_bytes());
+    let mut records = vec![];
+    for record in reader.records() {
+        let record = record.context("Failed to parse table data")?;
+        records.push(record.iter().map(str::to_string).collect::<Vec<_>>());
+    }
+    ensure!(
+        !records.is_empty(),
+        "Table {} has no rows",
+        options.file.as_deref().unwrap_or("data")
+    );
+
+    // Pad short rows so that every row has a cell for each column
+    let columns = records.iter().map(Vec::len).max().unwrap_or(0);
+    let children = records
+        .into_iter()
+        .map(|mut record| {
+            record.resize(columns, String::new());
+            Node::TableRow(TableRow {
+                children: record.into_iter().map(table_cell).collect(),
+                position: None,
+            })
+        })
+        .collect();
+
+    Ok(Table {
+        children,
+        position: None,
+        align: vec![AlignKind::None; columns],
+    })
+}
+
+fn table_cell(value: String) -> Node {
+    let value = value.trim();
+    let children = if value.is_empty() {
+        vec![]
+    } else {
+        vec![Node::Text(Text {
+            value: value.to_string(),
+            position: None,
+        })]
+    };
+    Node::TableCell(TableCell {
+        children,
+        position: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(value: &str) -> Code {
+        Code {
+            value: value.to_string(),
+            position: None,
+            lang: Some("csv".to_string()),
+            meta: None,
+        }
+    }
+
+    /// The text of every cell, row by row
+    fn cells(table: &Table) -> Vec<Vec<String>> {
+        table
+            .children
+            .iter()
+            .map(|row| {
+                row.children()
+                    .unwrap()
+                    .iter()
+                    .map(|cell| cell.to_string())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn short_rows_are_padded() {
+        let table = read_table(
+            &code("Name,Mass,Moons\nMercury,0.33\nEarth\n"),
+            b',',
+            &CsvTableOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(table.align.len(), 3);
+        assert_eq!(
+            cells(&table),
+            [
+                ["Name", "Mass", "Moons"],
+                ["Mercury", "0.33", ""],
+                ["Earth", "", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_fields_keep_delimiters_and_quotes() {
+        let table = read_table(
+            &code("Planet,Note\nSaturn,\"rings, \"\"lots\"\" of them\"\n"),
+            b',',
+            &CsvTableOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            cells(&table),
+            [["Planet", "Note"], ["Saturn", "rings, \"lots\" of them"]]
+        );
+    }
+
+    #[test]
+    fn tab_separated_fields_are_trimmed() {
+        let table = read_table(
+            &code("a\t b \nc, d\te\n"),
+            b'\t',
+            &CsvTableOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(cells(&table), [["a", "b"], ["c, d", "e"]]);
+    }
+
+    #[test]
+    fn only_blocks_with_a_file_or_table_option_are_tables() {
+        let is_table = |meta: Option<&str>| {
+            let code = Code {
+                meta: meta.map(str::to_string),
+                ..code("a,b\n")
+            };
+            options(&code)
+                .and_then(Result::ok)
+                .is_some_and(|o| o.is_table())
+        };
+        assert!(!is_table(None));
+        assert!(!is_table(Some(" ")));
+        assert!(!is_table(Some(r#"{"caption": "Data"}"#)));
+        assert!(!is_table(Some("not json")));
+        assert!(!is_table(Some(r#"{"table": false}"#)));
+        assert!(is_table(Some(r#"{"table": true}"#)));
+        assert!(is_table(Some(r#"{"file": "data.csv"}"#)));
+    }
+
+    #[test]
+    fn empty_data_is_an_error() {
+        let result = read_table(&code(""), b',', &CsvTableOptions::default(), None);
+        assert!(result.is_err());
+    }
+}
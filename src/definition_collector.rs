@@ -0,0 +1,81 @@
+use log::{debug, warn};
+use markdown::mdast::{Definition, FootnoteDefinition};
+
+use crate::traverser::MarkdownNodeTraverser;
+use std::collections::HashMap;
+
+/// The target of a link or image reference
+#[derive(Debug, Clone)]
+pub struct LinkTarget {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+/// Collects link reference definitions (`[id]: url "title"`) and footnote
+/// definitions (`[^id]: text`) so that references to them can be resolved by
+/// the emitter
+#[derive(Default, Debug, Clone)]
+pub struct DefinitionCollector {
+    definitions: HashMap<String, LinkTarget>,
+    footnotes: HashMap<String, FootnoteDefinition>,
+}
+
+impl DefinitionCollector {
+    pub fn get(&self, identifier: &str) -> Option<&LinkTarget> {
+        self.definitions.get(&normalize_identifier(identifier))
+    }
+
+    pub fn get_footnote(&self, identifier: &str) -> Option<&FootnoteDefinition> {
+        self.footnotes.get(&normalize_identifier(identifier))
+    }
+}
+
+/// Normalizes a reference label for case-insensitive, whitespace-collapsed matching
+fn normalize_identifier(identifier: &str) -> String {
+    identifier
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+impl MarkdownNodeTraverser for DefinitionCollector {
+    type Output = ();
+
+    fn visit_definition(&mut self, def: &Definition, _result: Self::Output) -> Self::Output {
+        debug!(
+            "First pass - collecting definition: identifier={}, url={}",
+            def.identifier, def.url
+        );
+        let identifier = normalize_identifier(&def.identifier);
+        if self.definitions.contains_key(&identifier) {
+            // As in CommonMark, the first definition of a label wins
+            warn!("Multiple defined link label: {}", def.identifier);
+            return;
+        }
+        self.definitions.insert(
+            identifier,
+            LinkTarget {
+                url: def.url.clone(),
+                title: def.title.clone(),
+            },
+        );
+    }
+
+    fn visit_footnote_definition(
+        &mut self,
+        def: &FootnoteDefinition,
+        _result: Self::Output,
+    ) -> Self::Output {
+        debug!(
+            "First pass - collecting footnote definition: identifier={}",
+            def.identifier
+        );
+        let identifier = normalize_identifier(&def.identifier);
+        if self.footnotes.contains_key(&identifier) {
+            warn!("Multiple defined footnote: {}", def.identifier);
+            return;
+        }
+        self.footnotes.insert(identifier, def.clone());
+    }
+}
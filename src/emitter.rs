@@ -6,14 +6,26 @@ use log::warn;
 use markdown::mdast;
 use markdown::mdast::Table;
 use markdown::mdast::{Heading, Node};
+use markdown::unist::Position;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::collections::HashSet;
 use std::mem;
 use std::path::PathBuf;
 
+use crate::csv_table::{self, CsvTableOptions};
+use crate::definition_collector::DefinitionCollector;
+use crate::highlighter;
 use crate::image_reference_collector::ImageReferenceCollector;
 use crate::metadata::ListType;
+use crate::metadata::ReferenceKind;
 use crate::metadata::StackCounter;
+use crate::metadata::bookmark_name;
+use crate::metadata::split_heading_attributes;
+use crate::options::{
+    CaptionPosition, CheckboxStyle, Options, TableBorderStyle, ThematicBreakStyle,
+};
+use crate::postprocessor::{CHECKBOX_CHECKED_ALIAS, CHECKBOX_UNCHECKED_ALIAS, PostProcessor};
 use crate::{
     image_reference_collector::ImageModifiers,
     metadata::TableMetadata,
@@ -21,17 +33,57 @@ use crate::{
     traverser::MarkdownNodeTraverser,
 };
 
+/// A block container whose paragraphs are collected instead of being added
+/// to the document body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContainerKind {
+    Footnote,
+    TableCell,
+}
+
+#[derive(Debug, Clone)]
+struct Container {
+    kind: ContainerKind,
+    paragraphs: Vec<docx_rs::Paragraph>,
+}
+
+/// The grid columns covered by a table cell and whether it continues a
+/// vertical merge from the cell above
+#[derive(Debug, Clone, Copy)]
+struct GridCell {
+    span: usize,
+    continues: bool,
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Emitter {
     strong_state: StackCounter,
     base_path: Option<PathBuf>,
     em_state: StackCounter,
+    strike_state: StackCounter,
     list_type: Vec<ListType>,
+    list_count: usize,
     reference: ImageReferenceCollector,
+    definitions: DefinitionCollector,
     table: Vec<docx_rs::TableRow>,
     table_metadata: Option<TableMetadata>,
+    table_alignment: Vec<AlignmentType>,
+    table_cells: Vec<Vec<GridCell>>,
+    table_column: usize,
+    imported_table: bool,
+    row_shading: Option<&'static str>,
     paragraph: docx_rs::Paragraph,
     paragraph_alignment: Option<AlignmentType>,
+    paragraph_flushed: bool,
+    code_styles: HashSet<String>,
+    options: Options,
+    code_theme: Option<&'static highlighter::Theme>,
+    link: Option<Hyperlink>,
+    link_field: bool,
+    quote_depth: usize,
+    containers: Vec<Container>,
+    bookmark_count: usize,
+    post_processor: PostProcessor,
 }
 
 impl Emitter {
@@ -46,6 +98,19 @@ impl Emitter {
         self.reference = reference;
     }
 
+    pub fn set_definitions(&mut self, definitions: DefinitionCollector) {
+        self.definitions = definitions;
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.code_theme = options.code_theme().and_then(highlighter::find_theme);
+        self.options = options;
+    }
+
+    pub fn post_processor(&self) -> &PostProcessor {
+        &self.post_processor
+    }
+
     // Handle document metadata (title, author)
     pub fn add_document_metadata(&self, metadata: &Option<Metadata>, mut docx: Docx) -> Docx {
         // Add title and author from metadata if available
@@ -82,238 +147,1104 @@ impl Emitter {
         docx
     }
 
-    // Handle insertion of images and return the updated docx
+    // Handle insertion of images and return the updated docx. Images numbered
+    // in the first pass get their own paragraph and a caption; others are
+    // placed inline. `url` is `None` for an undefined image reference
     fn handle_image(
         &mut self,
         docx: Docx,
-        url: &str,
+        url: Option<&str>,
         alt: &str,
         title: Option<&str>,
-        figure: &str,
+        position: Option<&Position>,
     ) -> Docx {
-        let img_url = url.to_string();
         let mut docx = docx;
+        // Alt text that holds modifiers is never caption text
+        let (res, alt_caption) = match serde_json::from_str::<ImageModifiers>(alt) {
+            Ok(modifiers) => (modifiers, ""),
+            Err(_) => (ImageModifiers::default(), alt),
+        };
+        let image = match url {
+            Some(url) => self.load_image(url, res.scale),
+            None => Err(alt.to_string()),
+        };
 
+        let Some(figure) = self.reference.figure_number(position) else {
+            let run = match image {
+                Ok(pic) => Run::new().add_image(pic),
+                Err(placeholder) => self.format_run(Run::new().add_text(placeholder).italic()),
+            };
+            self.push_run(run);
+            return docx;
+        };
+        debug!("Using figure number {} for {:?}", figure, res.r#ref);
+
+        // The image follows the text before it
+        docx = self.flush_paragraph(docx);
+
+        // Create a new paragraph with centered alignment. An image that
+        // cannot be loaded keeps its caption so that references to it resolve
+        let img_run = match image {
+            Ok(pic) => Run::new().add_image(pic),
+            Err(placeholder) => Run::new().add_text(placeholder).italic(),
+        };
+        let img_paragraph = docx_rs::Paragraph::new()
+            .add_run(img_run)
+            .align(AlignmentType::Center);
+
+        // Create a caption with the figure number
+        let display_title = title.unwrap_or(alt_caption);
+        let caption_paragraph = self.caption_paragraph(
+            ReferenceKind::Figure,
+            figure,
+            display_title,
+            res.r#ref.as_deref(),
+        );
+
+        // Add the image and caption to the document
+        self.emit_captioned(
+            docx,
+            ReferenceKind::Figure,
+            img_paragraph,
+            Some(caption_paragraph),
+        )
+    }
+
+    // Read an image relative to the base path, scaled by `scale`. Returns
+    // placeholder text if it cannot be read
+    fn load_image(&self, url: &str, scale: f64) -> Result<Pic, String> {
         // Try to resolve the image path
-        if let Some(base_dir) = &self.base_path {
-            let img_path = base_dir.join(&img_url);
-            debug!("Resolving image path: {}", img_path.display());
-
-            // Check if image exists
-            if img_path.exists() {
-                debug!("Image file found at: {}", img_path.display());
-                // Try to read the image file
-                match std::fs::read(&img_path) {
-                    Ok(buffer) => {
-                        let res: ImageModifiers =
-                            serde_json::from_str(alt).unwrap_or(ImageModifiers::default());
-                        debug!("Successfully read image file ({} bytes)", buffer.len());
-                        let (dim1, dim2) = get_image_dimensions(&img_path).unwrap();
-                        let (dim1, dim2) = (
-                            (dim1 as f64 * res.scale) as u32,
-                            (dim2 as f64 * res.scale) as u32,
-                        );
-
-                        // Reference handling is now done in the first pass
-                        if let Some(reference) = res.r#ref {
-                            debug!("Using reference: {} -> {}", reference, figure);
-                        } else {
-                            debug!("Image has no reference");
-                        }
+        let Some(base_dir) = &self.base_path else {
+            // No base path available, use placeholder text
+            warn!("No base path available to resolve image: {}", url);
+            return Err(format!("[Image: {}]", url));
+        };
+        let img_path = base_dir.join(url);
+        debug!("Resolving image path: {}", img_path.display());
+
+        // Check if image exists
+        if !img_path.exists() {
+            // If image doesn't exist, use placeholder text
+            warn!("Image file not found: {}", img_path.display());
+            return Err(format!("[Image: {} (not found)]", url));
+        }
+        debug!("Image file found at: {}", img_path.display());
+
+        // Try to read the image file
+        let buffer = std::fs::read(&img_path).map_err(|e| {
+            // If image couldn't be read, add placeholder text
+            warn!("Failed to read image file: {}", e);
+            format!("[Image: {} (could not read file)]", img_path.display())
+        })?;
+        debug!("Successfully read image file ({} bytes)", buffer.len());
+        let (dim1, dim2) = get_image_dimensions(&img_path).unwrap();
+        let (dim1, dim2) = ((dim1 as f64 * scale) as u32, (dim2 as f64 * scale) as u32);
+
+        // Create a Pic object from the image data
+        Ok(Pic::new(&buffer).size(dim1, dim2))
+    }
+
+    // The label of a numbered figure or table, e.g. "Figure 3"
+    fn reference_label(&self, kind: ReferenceKind, number: usize) -> String {
+        format!("{} {}", self.options.caption_format(kind).label, number)
+    }
+
+    // Create a caption paragraph for a figure or table. The number is a SEQ
+    // field, and a labeled caption bookmarks its label and number so that
+    // references to it can be REF fields. Captions placed above their object
+    // are kept on the same page as it
+    fn caption_paragraph(
+        &mut self,
+        kind: ReferenceKind,
+        number: usize,
+        title: &str,
+        r#ref: Option<&str>,
+    ) -> docx_rs::Paragraph {
+        // Only bookmark the caption the first pass numbered under this label
+        let bookmark = r#ref
+            .filter(|r#ref| self.reference.get(r#ref) == Some((kind, number)))
+            .map(|r#ref| (self.next_bookmark_id(), bookmark_name(r#ref)));
+        let format = self.options.caption_format(kind);
+        let identifier = match kind {
+            ReferenceKind::Figure => "Figure",
+            ReferenceKind::Table => "Table",
+        };
+        let format_run = |run: Run| if format.label_bold { run.bold() } else { run };
 
-                        // Create a Pic object from the image data
-                        // Use a standard image size (5 inches width max)
-                        let pic = Pic::new(&buffer).size(dim1, dim2);
+        let mut paragraph = docx_rs::Paragraph::new().style(CAPTION_STYLE);
+        if let Some((id, name)) = &bookmark {
+            paragraph = paragraph.add_bookmark_start(*id, name);
+        }
+        paragraph = paragraph
+            .add_run(format_run(
+                Run::new().add_text(format!("{} ", format.label)),
+            ))
+            .add_run(Run::new().add_field_char(FieldCharType::Begin, false))
+            .add_run(Run::new().add_instr_text(InstrText::Unsupported(format!(
+                " SEQ {} \\* ARABIC ",
+                identifier
+            ))))
+            .add_run(Run::new().add_field_char(FieldCharType::Separate, false))
+            .add_run(format_run(Run::new().add_text(number.to_string())))
+            .add_run(Run::new().add_field_char(FieldCharType::End, false));
+        if let Some((id, _)) = bookmark {
+            paragraph = paragraph.add_bookmark_end(id);
+        }
+        if !title.is_empty() {
+            paragraph =
+                paragraph.add_run(Run::new().add_text(format!("{}{}", format.separator, title)));
+        }
+        if format.position == CaptionPosition::Above {
+            paragraph = paragraph.keep_next(true);
+        }
+        paragraph
+    }
+
+    // Add a formatted heading and return the updated docx
+    fn add_heading(
+        &mut self,
+        docx: Docx,
+        children: &[Node],
+        level: u8,
+        position: Option<&Position>,
+    ) -> Docx {
+        let mut docx = docx;
+        let (children, attributes) = split_heading_attributes(children);
+        self.paragraph = docx_rs::Paragraph::new().style(&heading_style(level));
+        if attributes.unnumbered && self.options.number_sections() {
+            // Numbering ID 0 overrides the numbering of the heading style
+            let paragraph = mem::take(&mut self.paragraph);
+            self.paragraph = paragraph.numbering(NumberingId::new(0), IndentLevel::new(0));
+        }
 
-                        // Create a new paragraph with centered alignment
-                        let img_paragraph = docx_rs::Paragraph::new()
-                            .add_run(Run::new().add_image(pic))
-                            .align(AlignmentType::Center);
+        // Process inline children with the same run pipeline as paragraphs
+        // Labeled headings are bookmarked so that references can point to
+        // them, unless an earlier heading took the label
+        let owner = |id: &String| {
+            self.reference
+                .get_section(id)
+                .is_some_and(|section| Some(section.offset) == position.map(|p| p.start.offset))
+        };
+        let bookmark_id = attributes.id.as_ref().filter(|id| owner(id)).map(|id| {
+            let bookmark_id = self.next_bookmark_id();
+            let paragraph = mem::take(&mut self.paragraph);
+            self.paragraph = paragraph.add_bookmark_start(bookmark_id, bookmark_name(id));
+            bookmark_id
+        });
+
+        for child in &children {
+            docx = self.process_node(child, docx);
+        }
 
-                        // Add the image paragraph to the document
-                        docx = docx.add_paragraph(img_paragraph);
+        let mut heading_paragraph = mem::take(&mut self.paragraph);
+        if let Some(bookmark_id) = bookmark_id {
+            heading_paragraph = heading_paragraph.add_bookmark_end(bookmark_id);
+        }
+        self.emit_paragraph(docx, heading_paragraph)
+    }
 
-                        // Create a caption text with figure number
-                        let display_title = title.unwrap_or(alt);
-                        let caption_text = if !display_title.is_empty() {
-                            format!("Figure {}: {}", figure, display_title)
-                        } else {
-                            format!("Figure {}", figure)
-                        };
+    // Initialize the paragraph and character styles used by the emitter
+    pub fn initialize_styles(&self, docx: Docx) -> Docx {
+        // Monospaced, shaded paragraphs for code blocks
+        let mut source_code = Style::new(CODE_STYLE, StyleType::Paragraph)
+            .name("Source Code")
+            .based_on("Normal")
+            .fonts(code_fonts(self.options.code_font()))
+            .size(20)
+            .line_spacing(LineSpacing::new().before(0).after(0));
+        source_code.paragraph_property = source_code.paragraph_property.shading(
+            Shading::new()
+                .shd_type(ShdType::Clear)
+                .color("auto")
+                .fill(CODE_SHADING),
+        );
 
-                        // Add a centered caption below the image
-                        let caption_paragraph = docx_rs::Paragraph::new()
-                            .add_run(Run::new().add_text(caption_text).italic())
-                            .align(AlignmentType::Center);
+        // Monospaced character style for inline code
+        let inline_code = Style::new(INLINE_CODE_STYLE, StyleType::Character)
+            .name("Verbatim Char")
+            .fonts(code_fonts(self.options.code_font()));
+
+        // Character style for hyperlinks
+        let hyperlink = Style::new(HYPERLINK_STYLE, StyleType::Character)
+            .name("Hyperlink")
+            .color("0563C1")
+            .underline("single");
+
+        // Footnote text and superscript reference marks
+        let footnote_text = Style::new(FOOTNOTE_TEXT_STYLE, StyleType::Paragraph)
+            .name("footnote text")
+            .based_on("Normal")
+            .size(20)
+            .line_spacing(LineSpacing::new().before(0).after(0));
+        let mut footnote_reference =
+            Style::new(FOOTNOTE_REFERENCE_STYLE, StyleType::Character).name("footnote reference");
+        footnote_reference.run_property = footnote_reference
+            .run_property
+            .vert_align(VertAlignType::SuperScript);
+
+        // Indented quote paragraphs with a left border
+        let mut quote = Style::new(QUOTE_STYLE, StyleType::Paragraph)
+            .name("Quote")
+            .based_on("Normal")
+            .indent(
+                Some(BODY_INDENT + QUOTE_INDENT),
+                None,
+                Some(BODY_INDENT),
+                None,
+            )
+            .color("404040");
+        if self.options.quote_italic() {
+            quote = quote.italic();
+        }
+        quote.paragraph_property = quote.paragraph_property.set_borders(quote_borders());
+
+        // Centered captions for figures and tables
+        let caption = Style::new(CAPTION_STYLE, StyleType::Paragraph)
+            .name("caption")
+            .based_on("Normal")
+            .italic()
+            .size(20)
+            .color("44546A")
+            .align(AlignmentType::Center);
+
+        // Bold text for table header rows
+        let table_heading = Style::new(TABLE_HEADING_STYLE, StyleType::Paragraph)
+            .name("Table Heading")
+            .based_on("Normal")
+            .bold();
+
+        // Bold headings, visible to Word's navigation pane and outline view
+        let mut docx = docx;
+        for (level, size) in (1..=HEADING_LEVELS).zip(HEADING_SIZES) {
+            let mut heading = Style::new(heading_style(level), StyleType::Paragraph)
+                .name(format!("heading {}", level))
+                .based_on("Normal")
+                .next("Normal")
+                .size(size)
+                .bold()
+                .line_spacing(LineSpacing::new().before(240).after(120))
+                .outline_lvl(level as usize - 1);
+            heading.paragraph_property = heading.paragraph_property.keep_next(true);
+            if self.options.number_sections() {
+                heading.paragraph_property = heading.paragraph_property.numbering(
+                    NumberingId::new(HEADING_NUMBERING_ID),
+                    IndentLevel::new(level as usize - 1),
+                );
+            }
+            docx = docx.add_style(heading);
+        }
 
-                        docx = docx.add_paragraph(caption_paragraph);
-                    }
-                    Err(e) => {
-                        // If image couldn't be read, add placeholder text
-                        warn!("Failed to read image file: {}", e);
-                        let placeholder =
-                            format!("[Image: {} (could not read file)]", img_path.display());
-                        let placeholder_paragraph = docx_rs::Paragraph::new()
-                            .add_run(Run::new().add_text(placeholder).italic())
-                            .align(AlignmentType::Center);
-                        docx = docx.add_paragraph(placeholder_paragraph);
+        docx.add_style(source_code)
+            .add_style(inline_code)
+            .add_style(quote)
+            .add_style(hyperlink)
+            .add_style(footnote_text)
+            .add_style(footnote_reference)
+            .add_style(table_heading)
+            .add_style(caption)
+    }
+
+    // Add a paragraph to the innermost container, or to the document body
+    fn emit_paragraph(&mut self, docx: Docx, paragraph: docx_rs::Paragraph) -> Docx {
+        match self.containers.last_mut() {
+            Some(container) => {
+                container.paragraphs.push(paragraph);
+                docx
+            }
+            None => docx.add_paragraph(paragraph),
+        }
+    }
+
+    // Emit the paragraph built so far so that block content, such as an
+    // image, follows the text before it. The rest of the text continues in a
+    // new paragraph, aligned with the list item text inside lists
+    fn flush_paragraph(&mut self, docx: Docx) -> Docx {
+        let next = if self.list_type.is_empty() {
+            self.new_paragraph()
+        } else {
+            self.list_continuation_paragraph()
+        };
+        let paragraph = mem::replace(&mut self.paragraph, next);
+        self.paragraph_flushed = true;
+        // A list item paragraph still shows its bullet or number
+        if paragraph.children.is_empty() && !paragraph.has_numbering {
+            return docx;
+        }
+        self.emit_paragraph(docx, paragraph)
+    }
+
+    // Emit the paragraph being built, unless only block content was added
+    // to it since it was last flushed
+    fn finish_paragraph(&mut self, docx: Docx) -> Docx {
+        let paragraph = mem::take(&mut self.paragraph);
+        if mem::take(&mut self.paragraph_flushed) && paragraph.children.is_empty() {
+            return docx;
+        }
+        self.emit_paragraph(docx, paragraph)
+    }
+
+    // Add a table to the document body; containers cannot hold tables
+    fn emit_table(&mut self, docx: Docx, table: docx_rs::Table) -> Docx {
+        match self.containers.last() {
+            Some(container) => {
+                warn!("Tables are not supported in {:?} content", container.kind);
+                docx
+            }
+            None => docx.add_table(table),
+        }
+    }
+
+    fn in_container(&self, kind: ContainerKind) -> bool {
+        self.containers
+            .iter()
+            .any(|container| container.kind == kind)
+    }
+
+    // Create a paragraph for body text in the current container
+    fn new_paragraph(&self) -> docx_rs::Paragraph {
+        match self.containers.last().map(|container| container.kind) {
+            Some(ContainerKind::Footnote) => docx_rs::Paragraph::new().style(FOOTNOTE_TEXT_STYLE),
+            // Table cells are aligned by column, and header cells are bold
+            Some(ContainerKind::TableCell) => {
+                let mut paragraph = docx_rs::Paragraph::new();
+                if self.table_cells.len() == 1 {
+                    paragraph = paragraph.style(TABLE_HEADING_STYLE);
+                }
+                match self.table_alignment.get(self.table_column) {
+                    Some(alignment) => paragraph.align(*alignment),
+                    None => paragraph,
+                }
+            }
+            // Quotes are indented further at each nesting level
+            None if self.quote_depth > 0 => docx_rs::Paragraph::new().style(QUOTE_STYLE).indent(
+                Some(self.quote_indent()),
+                None,
+                Some(BODY_INDENT),
+                None,
+            ),
+            // Initialize a new paragraph with proper first line indentation
+            None => {
+                docx_rs::Paragraph::new().indent(Some(BODY_INDENT), None, Some(BODY_INDENT), None)
+            }
+        }
+    }
+
+    // Create a paragraph for list item text, in the quote style if the list
+    // is quoted
+    fn list_text_paragraph(&self) -> docx_rs::Paragraph {
+        if self.quote_depth > 0 {
+            docx_rs::Paragraph::new().style(QUOTE_STYLE)
+        } else {
+            docx_rs::Paragraph::new()
+        }
+    }
+
+    // Indent a list or code paragraph by `left`, shifted into any enclosing
+    // block quotes, which also draw their border beside it
+    fn indent_quoted(
+        &self,
+        paragraph: docx_rs::Paragraph,
+        left: i32,
+        special: Option<SpecialIndentType>,
+    ) -> docx_rs::Paragraph {
+        if self.quote_depth == 0 {
+            return paragraph.indent(Some(left), special, None, None);
+        }
+        let left = left + QUOTE_INDENT * self.quote_depth as i32;
+        let mut paragraph = paragraph.indent(Some(left), special, Some(BODY_INDENT), None);
+        paragraph.property = paragraph.property.set_borders(quote_borders());
+        paragraph
+    }
+
+    // Create a paragraph aligned with the text of the current list item
+    fn list_continuation_paragraph(&self) -> docx_rs::Paragraph {
+        let level = self.list_type.len().saturating_sub(1).min(LIST_LEVELS - 1);
+        self.indent_quoted(
+            self.list_text_paragraph(),
+            LIST_INDENT * (level as i32 + 1),
+            None,
+        )
+    }
+
+    // Create a task list item paragraph starting with a checkbox, indented
+    // like a list item at `level`
+    fn checkbox_paragraph(&self, checked: bool, level: usize) -> docx_rs::Paragraph {
+        let glyph = if checked { "\u{2612}" } else { "\u{2610}" };
+        let checkbox = Run::new().add_text(glyph).fonts(
+            RunFonts::new()
+                .ascii(CHECKBOX_FONT)
+                .hi_ansi(CHECKBOX_FONT)
+                .east_asia(CHECKBOX_FONT),
+        );
+
+        let paragraph = self.indent_quoted(
+            self.list_text_paragraph(),
+            LIST_INDENT * (level as i32 + 1),
+            Some(SpecialIndentType::Hanging(LIST_HANGING)),
+        );
+        let paragraph = match self.options.checkbox() {
+            CheckboxStyle::Glyph => paragraph.add_run(checkbox),
+            CheckboxStyle::Control => {
+                let alias = if checked {
+                    CHECKBOX_CHECKED_ALIAS
+                } else {
+                    CHECKBOX_UNCHECKED_ALIAS
+                };
+                paragraph.add_structured_data_tag(
+                    StructuredDataTag::new().alias(alias).add_run(checkbox),
+                )
+            }
+        };
+        paragraph.add_run(Run::new().add_tab())
+    }
+
+    // Add a table, with metadata given out of band or found in its last row.
+    // `number` is the table number from the first pass, if it has a caption
+    fn add_table(
+        &mut self,
+        table: &Table,
+        metadata: Option<TableMetadata>,
+        number: Option<usize>,
+        mut docx: Docx,
+    ) -> Docx {
+        self.table.clear();
+        self.table_cells.clear();
+        let external_metadata = metadata.is_some();
+        self.imported_table = external_metadata;
+        self.table_metadata = metadata;
+        self.table_alignment = table
+            .align
+            .iter()
+            .map(|alig| match alig {
+                mdast::AlignKind::None | mdast::AlignKind::Left => AlignmentType::Left,
+                mdast::AlignKind::Right => AlignmentType::Right,
+                mdast::AlignKind::Center => AlignmentType::Center,
+            })
+            .collect();
+        for (i, child) in table.children.iter().enumerate() {
+            let last = i == (table.children.len() - 1);
+            if let Node::TableRow(table_row) = child {
+                // The first row is the header; body rows may be banded
+                let header = i == 0;
+                self.row_shading = if header {
+                    Some(TABLE_HEADER_SHADING)
+                } else if i % 2 == 0 && self.options.table_banding() {
+                    Some(TABLE_BAND_SHADING)
+                } else {
+                    None
+                };
+                docx = self.visit_table_row(table_row, docx, last);
+                if header {
+                    // Keep the header row whole; it is marked as repeating
+                    // on each page during post-processing
+                    if let Some(row) = self.table.pop() {
+                        self.table.push(row.cant_split());
                     }
                 }
             } else {
-                // If image doesn't exist, use placeholder text
-                warn!("Image file not found: {}", img_path.display());
-                let placeholder = format!("[Image: {} (not found)]", img_url);
+                docx = self.process_child(child, docx);
+            }
+        }
+        let caption_paragraph = self.table_caption(self.table_metadata.clone(), number);
+        let caption_below = match caption_paragraph {
+            Some(caption_paragraph)
+                if self.options.caption_format(ReferenceKind::Table).position
+                    == CaptionPosition::Above =>
+            {
+                docx = self.emit_paragraph(docx, caption_paragraph);
+                None
+            }
+            caption_paragraph => caption_paragraph,
+        };
+        if caption_below.is_some() {
+            // Keep the last row on the same page as the caption below it
+            self.keep_last_row_with_next();
+        }
+        let rows = std::mem::take(&mut self.table);
+        let metadata_row = !external_metadata && self.table_metadata.is_some();
+        let table = self.size_table(
+            docx_rs::Table::new(rows).set_borders(table_borders(self.options.table_borders())),
+            table,
+            metadata_row,
+        );
+        docx = self.emit_table(docx, table);
+        match caption_below {
+            Some(caption_paragraph) => self.emit_paragraph(docx, caption_paragraph),
+            None => docx,
+        }
+    }
+
+    // Add a table imported from a CSV/TSV file or block
+    fn add_csv_table(
+        &mut self,
+        code: &mdast::Code,
+        delimiter: u8,
+        options: CsvTableOptions,
+        docx: Docx,
+    ) -> Docx {
+        let number = self.reference.table_number(code.position.as_ref());
+        match csv_table::read_table(code, delimiter, &options, self.base_path.as_deref()) {
+            Ok(table) => self.add_table(&table, Some(options.table_metadata()), number, docx),
+            Err(e) => {
+                warn!("{:#}", e);
+                let placeholder = format!(
+                    "[Table: {} (could not read)]",
+                    options.file.as_deref().unwrap_or("inline data")
+                );
                 let placeholder_paragraph = docx_rs::Paragraph::new()
                     .add_run(Run::new().add_text(placeholder).italic())
                     .align(AlignmentType::Center);
-                docx = docx.add_paragraph(placeholder_paragraph);
+                // Keep the caption so that the table keeps its number
+                let caption_paragraph = self.table_caption(Some(options.table_metadata()), number);
+                self.emit_captioned(
+                    docx,
+                    ReferenceKind::Table,
+                    placeholder_paragraph,
+                    caption_paragraph,
+                )
             }
+        }
+    }
+
+    // Create the caption of a table whose metadata has one, numbered by the
+    // first pass
+    fn table_caption(
+        &mut self,
+        metadata: Option<TableMetadata>,
+        number: Option<usize>,
+    ) -> Option<docx_rs::Paragraph> {
+        let TableMetadata {
+            caption: Some(caption),
+            r#ref,
+            ..
+        } = metadata?
+        else {
+            return None;
+        };
+        let Some(number) = number else {
+            warn!("Table '{}' was not numbered and has no caption", caption);
+            return None;
+        };
+        info!(
+            "{}: {}",
+            self.reference_label(ReferenceKind::Table, number),
+            caption
+        );
+        Some(self.caption_paragraph(ReferenceKind::Table, number, &caption, r#ref.as_deref()))
+    }
+
+    // Emit a figure or placeholder paragraph with its caption, keeping them
+    // on the same page
+    fn emit_captioned(
+        &mut self,
+        docx: Docx,
+        kind: ReferenceKind,
+        paragraph: docx_rs::Paragraph,
+        caption_paragraph: Option<docx_rs::Paragraph>,
+    ) -> Docx {
+        let Some(caption_paragraph) = caption_paragraph else {
+            return self.emit_paragraph(docx, paragraph);
+        };
+        match self.options.caption_format(kind).position {
+            CaptionPosition::Above => {
+                let docx = self.emit_paragraph(docx, caption_paragraph);
+                self.emit_paragraph(docx, paragraph)
+            }
+            CaptionPosition::Below => {
+                let docx = self.emit_paragraph(docx, paragraph.keep_next(true));
+                self.emit_paragraph(docx, caption_paragraph)
+            }
+        }
+    }
+
+    // Create an empty table cell, shaded for the current row
+    fn table_cell(&self) -> TableCell {
+        let tcell = TableCell::new();
+        match self.row_shading {
+            Some(fill) => tcell.shading(
+                Shading::new()
+                    .shd_type(ShdType::Clear)
+                    .color("auto")
+                    .fill(fill),
+            ),
+            None => tcell,
+        }
+    }
+
+    // Add a cell covering one grid column to the current table row
+    fn push_table_cell(&mut self, tcell: TableCell, continues: bool) {
+        self.table
+            .iter_mut()
+            .last()
+            .unwrap()
+            .cells
+            .push(TableRowChild::TableCell(tcell));
+        self.table_cells
+            .iter_mut()
+            .last()
+            .unwrap()
+            .push(GridCell { span: 1, continues });
+    }
+
+    // Extend the previous cell of the current row by one grid column.
+    // Returns false if there is no previous cell
+    fn merge_left(&mut self) -> bool {
+        let (Some(row), Some(cells)) = (self.table.last_mut(), self.table_cells.last_mut()) else {
+            return false;
+        };
+        let (Some(TableRowChild::TableCell(tcell)), Some(grid_cell)) =
+            (row.cells.last_mut(), cells.last_mut())
+        else {
+            return false;
+        };
+        grid_cell.span += 1;
+        *tcell = tcell.clone().grid_span(grid_cell.span);
+        true
+    }
+
+    // Continue the cell above the current grid column with a vertically merged
+    // cell. Returns false if no cell above starts at this grid column
+    fn merge_above(&mut self) -> bool {
+        let rows = self.table_cells.len();
+        if rows < 2 {
+            return false;
+        }
+        let grid_column: usize = self.table_cells[rows - 1].iter().map(|c| c.span).sum();
+        let mut start = 0;
+        let mut above = None;
+        for (i, grid_cell) in self.table_cells[rows - 2].iter().enumerate() {
+            if start == grid_column {
+                above = Some(i);
+                break;
+            }
+            start += grid_cell.span;
+        }
+        let Some(above) = above else {
+            return false;
+        };
+        if !self.table_cells[rows - 2][above].continues {
+            let TableRowChild::TableCell(tcell) = &mut self.table[rows - 2].cells[above];
+            *tcell = tcell.clone().vertical_merge(VMergeType::Restart);
+        }
+        let tcell = self
+            .table_cell()
+            .vertical_merge(VMergeType::Continue)
+            .add_paragraph(docx_rs::Paragraph::new());
+        self.push_table_cell(tcell, true);
+        true
+    }
+
+    // Keep the paragraphs of the last table row with the next paragraph
+    fn keep_last_row_with_next(&mut self) {
+        let Some(row) = self.table.last_mut() else {
+            return;
+        };
+        for TableRowChild::TableCell(tcell) in row.cells.iter_mut() {
+            for content in tcell.children.iter_mut() {
+                if let TableCellContent::Paragraph(paragraph) = content {
+                    paragraph.property.keep_next = Some(true);
+                }
+            }
+        }
+    }
+
+    // Set the table grid and width. Columns are sized from the metadata widths
+    // if given, or else from the length of their text, to fill the text width
+    fn size_table(
+        &self,
+        table: docx_rs::Table,
+        content: &Table,
+        metadata_row: bool,
+    ) -> docx_rs::Table {
+        let columns = content.align.len();
+        let metadata = self.table_metadata.as_ref();
+        let widths = match metadata.and_then(|metadata| metadata.widths.as_ref()) {
+            Some(widths) if widths.len() == columns && widths.iter().all(|w| *w > 0.0) => {
+                widths.clone()
+            }
+            Some(widths) => {
+                warn!(
+                    "Ignoring table widths {:?}: expected {} positive widths",
+                    widths, columns
+                );
+                content_widths(content, columns, metadata_row)
+            }
+            None => content_widths(content, columns, metadata_row),
+        };
+        let total: f32 = widths.iter().sum();
+        let grid = widths
+            .iter()
+            .map(|width| (TEXT_WIDTH as f32 * width / total) as usize)
+            .collect();
+        let table = table.set_grid(grid);
+        if metadata
+            .and_then(|metadata| metadata.autofit)
+            .unwrap_or(true)
+        {
+            // Autofit to the window: the table spans the text width and Word
+            // may adjust the columns to their content
+            table
+                .width(FULL_WIDTH_PCT, WidthType::Pct)
+                .layout(TableLayoutType::Autofit)
         } else {
-            // No base path available, use placeholder text
-            warn!("No base path available to resolve image: {}", img_url);
-            let placeholder = format!("[Image: {}]", img_url);
-            let placeholder_paragraph = docx_rs::Paragraph::new()
-                .add_run(Run::new().add_text(placeholder).italic())
-                .align(AlignmentType::Center);
-            docx = docx.add_paragraph(placeholder_paragraph);
+            table
+                .width(TEXT_WIDTH, WidthType::Dxa)
+                .layout(TableLayoutType::Fixed)
         }
+    }
 
-        docx
+    // Create a paragraph for a line of code, indented with any enclosing list
+    // item or quote
+    fn code_paragraph(&self, style: &str) -> docx_rs::Paragraph {
+        let paragraph = docx_rs::Paragraph::new().style(style);
+        if !self.list_type.is_empty() {
+            // Align code in list items with the item text
+            let level = self.list_type.len().min(LIST_LEVELS) as i32;
+            self.indent_quoted(paragraph, LIST_INDENT * level, None)
+        } else if self.quote_depth > 0 {
+            self.indent_quoted(paragraph, BODY_INDENT, None)
+        } else {
+            paragraph
+        }
     }
 
-    // Add a formatted heading and return the updated docx
-    fn add_heading(&self, docx: Docx, text: &str, level: u8) -> Docx {
-        let size = match level {
-            1 => 36,
-            2 => 28,
-            3 => 24,
-            _ => 20,
+    // Left indentation of block quote content at the current nesting depth
+    fn quote_indent(&self) -> i32 {
+        BODY_INDENT + QUOTE_INDENT * self.quote_depth as i32
+    }
+
+    // Render block content into a container, isolating it from the inline
+    // state of the paragraph currently being built
+    fn render_container(
+        &mut self,
+        kind: ContainerKind,
+        children: &[Node],
+        mut docx: Docx,
+    ) -> (Vec<docx_rs::Paragraph>, Docx) {
+        let paragraph = mem::take(&mut self.paragraph);
+        let paragraph_alignment = self.paragraph_alignment.take();
+        let paragraph_flushed = mem::take(&mut self.paragraph_flushed);
+        let link = self.link.take();
+        let link_field = mem::take(&mut self.link_field);
+        let strong_state = mem::take(&mut self.strong_state);
+        let em_state = mem::take(&mut self.em_state);
+        let strike_state = mem::take(&mut self.strike_state);
+        let list_type = mem::take(&mut self.list_type);
+
+        self.containers.push(Container {
+            kind,
+            paragraphs: vec![],
+        });
+        for child in children {
+            docx = self.process_node(child, docx);
+        }
+        let container = self.containers.pop().unwrap();
+
+        self.paragraph = paragraph;
+        self.paragraph_alignment = paragraph_alignment;
+        self.paragraph_flushed = paragraph_flushed;
+        self.link = link;
+        self.link_field = link_field;
+        self.strong_state = strong_state;
+        self.em_state = em_state;
+        self.strike_state = strike_state;
+        self.list_type = list_type;
+
+        (container.paragraphs, docx)
+    }
+
+    // Apply the current inline formatting state to a run
+    fn format_run(&self, mut run: Run) -> Run {
+        // Apply bold if in bold state
+        if self.strong_state.set() {
+            run = run.bold();
+        }
+
+        // Apply italic if in italic state
+        if self.em_state.set() {
+            run = run.italic();
+        }
+
+        // Apply strikethrough if in deleted text
+        if self.strike_state.set() {
+            run = run.strike();
+        }
+
+        run
+    }
+
+    // Add a run to the hyperlink or paragraph currently being built
+    fn push_run(&mut self, run: Run) {
+        if self.link_field {
+            let paragraph = std::mem::take(&mut self.paragraph);
+            self.paragraph = paragraph.add_run(run.style(HYPERLINK_STYLE));
+        } else if let Some(link) = self.link.take() {
+            self.link = Some(link.add_run(run.style(HYPERLINK_STYLE)));
+        } else {
+            let paragraph = std::mem::take(&mut self.paragraph);
+            self.paragraph = paragraph.add_run(run);
+        }
+    }
+
+    // Emit a hyperlink to `url` wrapping the runs produced by `children`
+    fn add_link(
+        &mut self,
+        url: &str,
+        title: Option<&str>,
+        children: &[Node],
+        mut docx: Docx,
+    ) -> Docx {
+        if self.link.is_some() || self.link_field {
+            // Word does not allow nested hyperlinks, so keep the outer one
+            debug!("Ignoring nested link to {}", url);
+            for child in children {
+                docx = self.process_node(child, docx);
+            }
+            return docx;
+        }
+
+        // `#label` links jump to the bookmark of a labeled heading, figure or table
+        let anchor = url.strip_prefix('#').map(|label| {
+            if !self.reference.is_defined(label) {
+                warn!("Link to undefined label: #{}", label);
+            }
+            bookmark_name(label)
+        });
+
+        if self.in_container(ContainerKind::Footnote) {
+            // The footnotes part has no relationships, so link with a HYPERLINK field
+            let mut instr = match &anchor {
+                Some(anchor) => format!(r#" HYPERLINK \l "{}""#, anchor),
+                None => format!(r#" HYPERLINK "{}""#, url),
+            };
+            if let Some(title) = title {
+                instr.push_str(&format!(r#" \o "{}""#, title.replace('"', "'")));
+            }
+            self.push_run(Run::new().add_field_char(FieldCharType::Begin, false));
+            self.push_run(Run::new().add_instr_text(InstrText::Unsupported(instr + " ")));
+            self.push_run(Run::new().add_field_char(FieldCharType::Separate, false));
+            self.link_field = true;
+            for child in children {
+                docx = self.process_node(child, docx);
+            }
+            self.link_field = false;
+            self.push_run(Run::new().add_field_char(FieldCharType::End, false));
+            return docx;
+        }
+
+        let link = match &anchor {
+            Some(anchor) => Hyperlink::new(anchor, HyperlinkType::Anchor),
+            None => Hyperlink::new(url, HyperlinkType::External),
         };
+        if let (Some(title), HyperlinkData::External { rid, .. }) = (title, &link.link) {
+            self.post_processor.add_hyperlink_tooltip(rid, title);
+        }
+
+        self.link = Some(link);
+        for child in children {
+            docx = self.process_node(child, docx);
+        }
+        if let Some(link) = self.link.take() {
+            let paragraph = std::mem::take(&mut self.paragraph);
+            self.paragraph = paragraph.add_hyperlink(link);
+        }
+        docx
+    }
 
-        let heading_paragraph =
-            docx_rs::Paragraph::new().add_run(Run::new().add_text(text).size(size).bold());
+    // Returns the paragraph style for a code block, registering a
+    // language-specific style derived from the base code style if needed
+    fn code_style(&mut self, lang: Option<&str>, docx: Docx) -> (String, Docx) {
+        let lang: String = lang
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        if lang.is_empty() {
+            return (String::from(CODE_STYLE), docx);
+        }
 
-        docx.add_paragraph(heading_paragraph)
+        let style_id = format!("{}{}", CODE_STYLE, lang);
+        if self.code_styles.insert(style_id.clone()) {
+            debug!("Adding code style: {}", style_id);
+            let style = Style::new(&style_id, StyleType::Paragraph)
+                .name(format!("Source Code ({})", lang))
+                .based_on(CODE_STYLE);
+            (style_id, docx.add_style(style))
+        } else {
+            (style_id, docx)
+        }
     }
 
     // Initialize numbering for lists based on the docx-rs API
     pub fn initialize_numbering(&self, docx: Docx) -> Docx {
-        // Create bullet list (ID: 1)
-        let docx = docx.add_abstract_numbering(
-            AbstractNumbering::new(1)
-                .add_level(
-                    Level::new(
-                        0,
-                        Start::new(1),
-                        NumberFormat::new("bullet"),
-                        LevelText::new("•"),
-                        LevelJc::new("left"),
-                    )
-                    .indent(
-                        Some(720),
-                        Some(SpecialIndentType::Hanging(360)),
-                        None,
-                        None,
-                    ),
+        let mut bullet = AbstractNumbering::new(BULLET_NUMBERING_ID);
+        let mut numbered = AbstractNumbering::new(ORDERED_NUMBERING_ID);
+
+        // Define every level Word supports, cycling through the bullet glyphs
+        // and numbering schemes as lists nest deeper
+        for level in 0..LIST_LEVELS {
+            let indent = LIST_INDENT * (level as i32 + 1);
+
+            // Create bullet list level
+            bullet = bullet.add_level(
+                Level::new(
+                    level,
+                    Start::new(1),
+                    NumberFormat::new("bullet"),
+                    LevelText::new(BULLET_GLYPHS[level % BULLET_GLYPHS.len()]),
+                    LevelJc::new("left"),
                 )
-                .add_level(
-                    Level::new(
-                        1,
-                        Start::new(1),
-                        NumberFormat::new("bullet"),
-                        LevelText::new("○"),
-                        LevelJc::new("left"),
-                    )
-                    .indent(
-                        Some(1440),
-                        Some(SpecialIndentType::Hanging(360)),
-                        None,
-                        None,
-                    ),
+                .indent(
+                    Some(indent),
+                    Some(SpecialIndentType::Hanging(LIST_HANGING)),
+                    None,
+                    None,
                 ),
-        );
-
-        // Create numbered list (ID: 2)
-        let docx = docx.add_abstract_numbering(
-            AbstractNumbering::new(2)
-                .add_level(
-                    Level::new(
-                        0,
-                        Start::new(1),
-                        NumberFormat::new("decimal"),
-                        LevelText::new("%1."),
-                        LevelJc::new("left"),
-                    )
-                    .indent(
-                        Some(720),
-                        Some(SpecialIndentType::Hanging(360)),
-                        None,
-                        None,
-                    ),
+            );
+
+            // Create numbered list level
+            let (format, suffix) = NUMBER_FORMATS[level % NUMBER_FORMATS.len()];
+            numbered = numbered.add_level(
+                Level::new(
+                    level,
+                    Start::new(1),
+                    NumberFormat::new(format),
+                    LevelText::new(format!("%{}{}", level + 1, suffix)),
+                    LevelJc::new("left"),
                 )
-                .add_level(
-                    Level::new(
-                        1,
-                        Start::new(1),
-                        NumberFormat::new("lowerLetter"),
-                        LevelText::new("%2)"),
-                        LevelJc::new("left"),
-                    )
-                    .indent(
-                        Some(1440),
-                        Some(SpecialIndentType::Hanging(360)),
-                        None,
-                        None,
-                    ),
+                .indent(
+                    Some(indent),
+                    Some(SpecialIndentType::Hanging(LIST_HANGING)),
+                    None,
+                    None,
                 ),
-        );
+            );
+        }
+        let docx = docx
+            .add_abstract_numbering(bullet)
+            .add_abstract_numbering(numbered);
 
         // Associate abstract numberings with concrete numberings
-        let docx = docx.add_numbering(Numbering::new(1, 1)); // Bullet list
-        let docx = docx.add_numbering(Numbering::new(2, 2)); // Numbered list
+        // Each ordered list gets its own instance of the numbered list in `visit_list`
+        let docx = docx.add_numbering(Numbering::new(BULLET_NUMBERING_ID, BULLET_NUMBERING_ID)); // Bullet list
 
-        docx
+        if self.options.number_sections() {
+            self.initialize_heading_numbering(docx)
+        } else {
+            docx
+        }
     }
 
-    // Main function to parse markdown and create a DOCX document
-    fn check_references(&self, text: &str) -> String {
-        let mut result = String::from(text);
-
-        // Find all references in the text
-        let mut matched_any = false;
-        let mut start_idx = 0;
+    // Define section numbers ("2.3.1") for the heading styles
+    fn initialize_heading_numbering(&self, docx: Docx) -> Docx {
+        let mut headings = AbstractNumbering::new(HEADING_NUMBERING_ID);
+        for level in 1..=HEADING_LEVELS {
+            let text = (1..=level)
+                .map(|l| format!("%{}", l))
+                .collect::<Vec<_>>()
+                .join(".");
+            headings = headings.add_level(
+                Level::new(
+                    level as usize - 1,
+                    Start::new(1),
+                    NumberFormat::new("decimal"),
+                    LevelText::new(text),
+                    LevelJc::new("left"),
+                )
+                .suffix(LevelSuffixType::Space)
+                .paragraph_style(heading_style(level)),
+            );
+        }
+        docx.add_abstract_numbering(headings)
+            .add_numbering(Numbering::new(HEADING_NUMBERING_ID, HEADING_NUMBERING_ID))
+    }
 
-        while let Some(reference_match) = REF_REGEX.find_at(&result, start_idx) {
-            matched_any = true;
-            let match_range = reference_match.start()..reference_match.end();
-            let reference_text = reference_match.as_str();
+    // Create a numbering instance for an ordered list so that it restarts at
+    // `start` instead of continuing the previous list
+    fn add_list_numbering(&mut self, start: u32, level: usize, docx: Docx) -> (usize, Docx) {
+        let numbering_id = FIRST_LIST_NUMBERING_ID + self.list_count;
+        self.list_count += 1;
+        debug!(
+            "Adding numbering {} for ordered list at level {} starting at {}",
+            numbering_id, level, start
+        );
+        let numbering = Numbering::new(numbering_id, ORDERED_NUMBERING_ID)
+            .add_override(LevelOverride::new(level).start(start as usize));
+        (numbering_id, docx.add_numbering(numbering))
+    }
 
-            if let Some(reference_key) = extract_ref(reference_text) {
-                if let Some(r#ref) = self.reference.get(reference_key) {
-                    // Replace the {ref:key} with "Figure X"
-                    debug!("Replacing reference '{}' with '{}'", reference_key, r#ref);
-                    let replacement = format!("Figure {}", r#ref);
-                    result.replace_range(match_range.clone(), &replacement);
+    fn next_bookmark_id(&mut self) -> usize {
+        self.bookmark_count += 1;
+        self.bookmark_count
+    }
 
-                    // Adjust the start index for the next search
-                    start_idx = match_range.start + replacement.len();
-                } else {
-                    warn!(
-                        "Reference '{}' not found in collected references",
-                        reference_key
-                    );
-                    start_idx = match_range.end;
-                }
+    // Add text to the current paragraph, replacing `{ref: ...}` references
+    // with REF fields to the bookmarked caption or heading, which Word can
+    // update
+    fn push_text(&mut self, text: &str) {
+        let mut pending = String::new();
+        let mut rest = 0;
+
+        for reference_match in REF_REGEX.find_iter(text) {
+            let Some(reference_key) = extract_ref(reference_match.as_str()).map(str::trim) else {
+                continue;
+            };
+            if let Some((kind, number)) = self.reference.get(reference_key) {
+                // Replace the {ref:key} with "Figure X" or "Table X"
+                let replacement = self.reference_label(kind, number);
+                debug!(
+                    "Replacing reference '{}' with '{}'",
+                    reference_key, replacement
+                );
+                pending.push_str(&text[rest..reference_match.start()]);
+                self.push_text_run(&mem::take(&mut pending));
+                let instr = format!(" REF {} \\h ", bookmark_name(reference_key));
+                self.push_field(&instr, &replacement);
+            } else if let Some(section) = self.reference.get_section(reference_key).cloned() {
+                // Refer to a section by number, or by its text if unnumbered
+                pending.push_str(&text[rest..reference_match.start()]);
+                let bookmark = bookmark_name(reference_key);
+                let (instr, result) = match section.number {
+                    Some(number) if self.options.number_sections() => {
+                        pending.push_str(self.options.section_label());
+                        pending.push(' ');
+                        (format!(" REF {} \\r \\h ", bookmark), number)
+                    }
+                    _ => (format!(" REF {} \\h ", bookmark), section.text),
+                };
+                debug!("Replacing reference '{}' with '{}'", reference_key, result);
+                self.push_text_run(&mem::take(&mut pending));
+                self.push_field(&instr, &result);
             } else {
-                start_idx = match_range.end;
+                warn!(
+                    "Reference '{}' not found in collected references",
+                    reference_key
+                );
+                continue;
             }
+            rest = reference_match.end();
         }
 
-        if !matched_any {
-            // No references found, return the original text
-            return String::from(text);
+        pending.push_str(&text[rest..]);
+        self.push_text_run(&pending);
+    }
+
+    // Add a run of formatted text to the current paragraph
+    fn push_text_run(&mut self, text: &str) {
+        if !text.is_empty() {
+            let run = self.format_run(Run::new().add_text(text));
+            self.push_run(run);
         }
-        result
+    }
+
+    // Add a field with instruction `instr`, showing `result` until Word
+    // updates it
+    fn push_field(&mut self, instr: &str, result: &str) {
+        self.push_run(Run::new().add_field_char(FieldCharType::Begin, false));
+        self.push_run(Run::new().add_instr_text(InstrText::Unsupported(instr.to_string())));
+        self.push_run(Run::new().add_field_char(FieldCharType::Separate, false));
+        self.push_text_run(result);
+        self.push_run(Run::new().add_field_char(FieldCharType::End, false));
     }
 }
 
@@ -321,15 +1252,12 @@ impl MarkdownNodeTraverser for Emitter {
     type Output = Docx;
 
     fn visit_heading(&mut self, heading: &Heading, docx: Docx) -> Docx {
-        let mut text = String::new();
-        for child in &heading.children {
-            if let Node::Text(text_node) = child {
-                text.push_str(&text_node.value);
-            } else {
-                warn!("Found non-text node in Heading: {:?}", child);
-            }
-        }
-        self.add_heading(docx, &text, heading.depth)
+        self.add_heading(
+            docx,
+            &heading.children,
+            heading.depth,
+            heading.position.as_ref(),
+        )
     }
 
     fn visit_image(&mut self, image: &mdast::Image, docx: Docx) -> Docx {
@@ -337,59 +1265,159 @@ impl MarkdownNodeTraverser for Emitter {
             "Processing image: url={}, alt={}, title={:?}",
             image.url, image.alt, image.title
         );
-
-        // Get figure number from the image reference or generate a new one
-        let res: ImageModifiers =
-            serde_json::from_str(&image.alt).unwrap_or(ImageModifiers::default());
-
-        let figure = if let Some(reference) = &res.r#ref {
-            // Use the figure number from the first pass
-            self.reference.get(reference).unwrap_or(String::from("??"))
-        } else {
-            // For images without references, use the position in the document
-            String::from("??")
-        };
-
         self.handle_image(
             docx,
-            &image.url,
+            Some(&image.url),
             &image.alt,
             image.title.as_deref(),
-            &figure,
+            image.position.as_ref(),
         )
     }
 
+    fn visit_image_reference(&mut self, img_ref: &mdast::ImageReference, docx: Docx) -> Docx {
+        debug!(
+            "Processing image reference: identifier={}, alt={}",
+            img_ref.identifier, img_ref.alt
+        );
+        let target = self.definitions.get(&img_ref.identifier).cloned();
+        if target.is_none() {
+            warn!(
+                "Undefined image reference: [{}]",
+                img_ref.label.as_deref().unwrap_or(&img_ref.identifier)
+            );
+        }
+        self.handle_image(
+            docx,
+            target.as_ref().map(|target| target.url.as_str()),
+            &img_ref.alt,
+            target.as_ref().and_then(|target| target.title.as_deref()),
+            img_ref.position.as_ref(),
+        )
+    }
+
+    fn visit_link_reference(&mut self, link_ref: &mdast::LinkReference, mut docx: Docx) -> Docx {
+        debug!(
+            "Processing link reference: identifier={}",
+            link_ref.identifier
+        );
+        match self.definitions.get(&link_ref.identifier).cloned() {
+            Some(target) => self.add_link(
+                &target.url,
+                target.title.as_deref(),
+                &link_ref.children,
+                docx,
+            ),
+            None => {
+                warn!(
+                    "Undefined link reference: [{}]",
+                    link_ref.label.as_deref().unwrap_or(&link_ref.identifier)
+                );
+                for child in &link_ref.children {
+                    docx = self.process_node(child, docx);
+                }
+                docx
+            }
+        }
+    }
+
     fn visit_text(&mut self, text: &mdast::Text, docx: Docx) -> Docx {
         // Process the text value to ensure proper spacing
         // First, ensure there's a space between words that were separated by newlines
         let with_spaces = text.value.replace("\n", " ");
 
-        // Then normalize any multiple spaces that might have been created
-        let normalized_text = with_spaces
+        // Then normalize any multiple spaces that might have been created,
+        // keeping a single leading/trailing space to separate adjacent runs
+        let mut normalized_text = with_spaces
             .split_whitespace()
             .collect::<Vec<&str>>()
             .join(" ");
+        if with_spaces.starts_with(char::is_whitespace) {
+            normalized_text.insert(0, ' ');
+        }
+        if with_spaces.ends_with(char::is_whitespace) && !normalized_text.trim_end().is_empty() {
+            normalized_text.push(' ');
+        }
 
-        // Finally check for references
-        let textval = self.check_references(&normalized_text);
+        // Finally resolve references and add the formatted runs to the
+        // current paragraph
+        self.push_text(&normalized_text);
 
-        // Create a run with appropriate formatting based on current state
-        let mut run = Run::new().add_text(&textval);
+        docx
+    }
 
-        // Apply bold if in bold state
-        if self.strong_state.set() {
-            run = run.bold();
+    fn visit_blockquote(&mut self, blockquote: &mdast::Blockquote, mut docx: Docx) -> Docx {
+        self.quote_depth += 1;
+        for child in &blockquote.children {
+            docx = self.process_node(child, docx);
         }
+        self.quote_depth -= 1;
+        docx
+    }
 
-        // Apply italic if in italic state
-        if self.em_state.set() {
-            run = run.italic();
+    fn visit_footnote_reference(
+        &mut self,
+        ref_node: &mdast::FootnoteReference,
+        docx: Docx,
+    ) -> Docx {
+        debug!("Processing footnote reference: {}", ref_node.identifier);
+        if self.in_container(ContainerKind::Footnote) {
+            warn!(
+                "Ignoring footnote reference [^{}] inside a footnote",
+                ref_node.identifier
+            );
+            return docx;
+        }
+        let Some(definition) = self.definitions.get_footnote(&ref_node.identifier).cloned() else {
+            warn!("Undefined footnote: [^{}]", ref_node.identifier);
+            return docx;
+        };
+
+        let (paragraphs, docx) =
+            self.render_container(ContainerKind::Footnote, &definition.children, docx);
+        let mut footnote = Footnote::new();
+        for paragraph in paragraphs {
+            footnote = footnote.add_content(paragraph);
         }
+        self.push_run(Run::new().add_footnote_reference(footnote));
+        docx
+    }
+
+    fn visit_link(&mut self, link: &mdast::Link, docx: Docx) -> Docx {
+        debug!("Processing link: url={}, title={:?}", link.url, link.title);
+        self.add_link(&link.url, link.title.as_deref(), &link.children, docx)
+    }
 
-        // Add the formatted run to the current paragraph
-        let paragraph = std::mem::take(&mut self.paragraph);
-        self.paragraph = paragraph.add_run(run);
+    fn visit_inline_code(&mut self, code: &mdast::InlineCode, docx: Docx) -> Docx {
+        let mut run = Run::new().style(INLINE_CODE_STYLE).add_text(&code.value);
+        if self.options.inline_code_shading() {
+            run = run.shading(
+                Shading::new()
+                    .shd_type(ShdType::Clear)
+                    .color("auto")
+                    .fill(CODE_SHADING),
+            );
+        }
+        let run = self.format_run(run);
+        self.push_run(run);
+        docx
+    }
 
+    fn visit_break(&mut self, _break_node: &mdast::Break, docx: Docx) -> Docx {
+        self.push_run(Run::new().add_break(BreakType::TextWrapping));
+        docx
+    }
+
+    fn visit_html(&mut self, html: &mdast::Html, docx: Docx) -> Docx {
+        // `<br>` is the only way to break lines in table cells
+        if LINE_BREAK_REGEX.is_match(&html.value) {
+            return self.visit_break(
+                &mdast::Break {
+                    position: html.position.clone(),
+                },
+                docx,
+            );
+        }
+        debug!("Ignoring HTML: {}", html.value);
         docx
     }
 
@@ -411,10 +1439,46 @@ impl MarkdownNodeTraverser for Emitter {
         docx
     }
 
+    fn visit_thematic_break(&mut self, _break_node: &mdast::ThematicBreak, docx: Docx) -> Docx {
+        let paragraph = match self.options.thematic_break() {
+            ThematicBreakStyle::Page if self.containers.is_empty() => {
+                docx_rs::Paragraph::new().add_run(Run::new().add_break(BreakType::Page))
+            }
+            _ => {
+                // An empty paragraph whose bottom border draws the rule
+                let mut paragraph = docx_rs::Paragraph::new();
+                paragraph.property = paragraph.property.set_borders(
+                    ParagraphBorders::with_empty().set(
+                        ParagraphBorder::new(ParagraphBorderPosition::Bottom)
+                            .val(BorderType::Single)
+                            .size(6)
+                            .space(1)
+                            .color("auto"),
+                    ),
+                );
+                paragraph
+            }
+        };
+        self.emit_paragraph(docx, paragraph)
+    }
+
+    fn visit_delete(&mut self, del: &mdast::Delete, mut docx: Docx) -> Docx {
+        self.strike_state.push();
+        for node in del.children.iter() {
+            docx = self.process_node(node, docx);
+        }
+        self.strike_state.pop();
+        docx
+    }
+
     fn visit_list(&mut self, list: &mdast::List, mut docx: Docx) -> Docx {
         // Determine list type (ordered/numbered or unordered/bullet)
         if list.ordered {
-            self.list_type.push(ListType::Ordered);
+            let level = self.list_type.len().min(LIST_LEVELS - 1);
+            let (numbering_id, updated) =
+                self.add_list_numbering(list.start.unwrap_or(1), level, docx);
+            docx = updated;
+            self.list_type.push(ListType::Ordered(numbering_id));
         } else {
             self.list_type.push(ListType::Unordered);
         }
@@ -429,57 +1493,88 @@ impl MarkdownNodeTraverser for Emitter {
     }
 
     fn visit_list_item(&mut self, list_item: &mdast::ListItem, mut docx: Docx) -> Docx {
-        if list_item.checked.is_some() {
-            debug!("Check Boxes not yet supported");
-        }
-        if list_item.spread {
-            debug!("Spread list items not yet supported");
-        }
-
         if self.list_type.is_empty() {
             debug!("List item found outside of a list context");
             return docx;
         }
 
         let numbering_id = match self.list_type.last().unwrap() {
-            ListType::Ordered => 2,   // Numbered list
-            ListType::Unordered => 1, // Bullet list
+            ListType::Ordered(numbering_id) => *numbering_id, // Numbered list
+            ListType::Unordered => BULLET_NUMBERING_ID,       // Bullet list
         };
-        let indent_level = self.list_type.len() - 1;
+        let mut indent_level = self.list_type.len() - 1;
+        if indent_level >= LIST_LEVELS {
+            warn!(
+                "Lists nested deeper than {} levels are rendered at the deepest level",
+                LIST_LEVELS
+            );
+            indent_level = LIST_LEVELS - 1;
+        }
 
-        // Create a paragraph with numbering properties
-        self.paragraph = docx_rs::Paragraph::new().numbering(
-            NumberingId::new(numbering_id),
-            IndentLevel::new(indent_level),
-        );
+        self.paragraph = match list_item.checked {
+            // Task list items show a checkbox in place of the bullet
+            Some(checked) => self.checkbox_paragraph(checked, indent_level),
+            // Create a paragraph with numbering properties. The numbering
+            // indents the item unless it is shifted into a block quote
+            None => {
+                let paragraph = self.list_text_paragraph().numbering(
+                    NumberingId::new(numbering_id),
+                    IndentLevel::new(indent_level),
+                );
+                if self.quote_depth > 0 {
+                    self.indent_quoted(
+                        paragraph,
+                        LIST_INDENT * (indent_level as i32 + 1),
+                        Some(SpecialIndentType::Hanging(LIST_HANGING)),
+                    )
+                } else {
+                    paragraph
+                }
+            }
+        };
 
-        // Process the content of the list item and add to the paragraph
+        // Process the content of the list item. The first paragraph carries
+        // the bullet or number; everything else follows it in source order
+        let mut numbered_emitted = false;
         for child in &list_item.children {
             match child {
                 Node::Paragraph(para) => {
-                    // For paragraph nodes in list items, process their children directly
-                    // This avoids creating a new paragraph
+                    if numbered_emitted {
+                        // Continuation paragraphs are aligned with the item text
+                        self.paragraph = self.list_continuation_paragraph();
+                    }
+                    // Process each child node which will add runs to self.paragraph
+                    self.paragraph_flushed = false;
                     for para_child in &para.children {
-                        // Process each child node which will add runs to self.paragraph
                         docx = self.process_node(para_child, docx);
                     }
+                    docx = self.finish_paragraph(docx);
+                    numbered_emitted = true;
                 }
                 _ => {
-                    // For other types of content, process recursively
-                    // This handles nested lists, code blocks, etc.
+                    // Flush the numbered paragraph so that nested lists, code
+                    // blocks, etc. appear after the text that precedes them
+                    if !numbered_emitted {
+                        let paragraph = mem::take(&mut self.paragraph);
+                        docx = self.emit_paragraph(docx, paragraph);
+                        numbered_emitted = true;
+                    }
                     docx = self.process_node(child, docx);
                 }
             }
         }
 
-        // Add the list item paragraph to the document
-        docx.add_paragraph(mem::take(&mut self.paragraph))
+        // An empty list item still shows its bullet or number
+        if !numbered_emitted {
+            let paragraph = mem::take(&mut self.paragraph);
+            docx = self.emit_paragraph(docx, paragraph);
+        }
+        docx
     }
 
     fn visit_paragraph(&mut self, para: &mdast::Paragraph, mut docx: Self::Output) -> Self::Output {
-        // Initialize a new paragraph with proper first line indentation
-        let paragraph = docx_rs::Paragraph::new().indent(Some(720), None, Some(720), None);
-        self.paragraph = paragraph;
+        self.paragraph = self.new_paragraph();
+        self.paragraph_flushed = false;
 
         // Reset paragraph alignment
         self.paragraph_alignment = None;
@@ -496,38 +1591,74 @@ impl MarkdownNodeTraverser for Emitter {
         }
 
         // Add the complete paragraph to the document
-        docx.add_paragraph(mem::take(&mut self.paragraph))
+        self.finish_paragraph(docx)
     }
 
-    fn visit_table(&mut self, table: &Table, mut docx: Self::Output) -> Self::Output {
-        self.table.clear();
-        self.table_metadata = None;
-        let _table_alignment: Vec<AlignmentType> = table
-            .align
-            .iter()
-            .map(|alig| match alig {
-                mdast::AlignKind::None | mdast::AlignKind::Left => AlignmentType::Left,
-                mdast::AlignKind::Right => AlignmentType::Right,
-                mdast::AlignKind::Center => AlignmentType::Center,
-            })
-            .collect();
-        for (i, child) in table.children.iter().enumerate() {
-            let last = i == (table.children.len() - 1);
-            if let Node::TableRow(table_row) = child {
-                docx = self.visit_table_row(table_row, docx, last);
-            } else {
-                docx = self.process_child(child, docx);
+    fn visit_code(&mut self, code: &mdast::Code, docx: Self::Output) -> Self::Output {
+        debug!("Processing code block: lang={:?}", code.lang);
+        // A csv/tsv block imports a table only if its options ask for one
+        if let Some(delimiter) = csv_table::delimiter(code) {
+            match csv_table::options(code) {
+                Some(Ok(options)) if options.is_table() => {
+                    return self.add_csv_table(code, delimiter, options, docx);
+                }
+                Some(Ok(_)) => warn!(
+                    "Showing table data as code, its options set neither `file` nor `table`: {:?}",
+                    code.meta
+                ),
+                Some(Err(e)) => warn!(
+                    "Showing table data as code, invalid table options {:?}: {}",
+                    code.meta, e
+                ),
+                None => {}
             }
         }
-        if let Some(table_metadata) = &self.table_metadata {
-            let caption = table_metadata.to_string(&self.reference);
-            info!("{}", caption);
-            let caption_paragraph = docx_rs::Paragraph::new()
-                .add_run(Run::new().add_text(caption).italic())
-                .align(AlignmentType::Center);
-            docx = docx.add_paragraph(caption_paragraph);
+        let (style, mut docx) = self.code_style(code.lang.as_deref(), docx);
+
+        // Highlight the block if it declares a language and a theme is selected
+        let highlighted = match (code.lang.as_deref(), self.code_theme) {
+            (Some(lang), Some(theme)) => highlighter::highlight(&code.value, lang, theme)
+                .map(|lines| (lines, theme.settings.background)),
+            _ => None,
+        };
+
+        // Emit one paragraph per source line so that line structure survives.
+        // An empty block still gets one empty line
+        if let Some((mut lines, background)) = highlighted {
+            if lines.is_empty() {
+                lines.push(vec![]);
+            }
+            for tokens in lines {
+                let mut paragraph = self.code_paragraph(&style);
+                if let Some(background) = background {
+                    paragraph.property = paragraph.property.shading(
+                        Shading::new()
+                            .shd_type(ShdType::Clear)
+                            .color("auto")
+                            .fill(highlighter::hex_color(background)),
+                    );
+                }
+                for (token_style, text) in tokens {
+                    paragraph = paragraph.add_run(highlighted_run(&text, token_style));
+                }
+                docx = self.emit_paragraph(docx, paragraph);
+            }
+        } else {
+            let lines = match code.value.as_str() {
+                "" => vec![""],
+                value => value.lines().collect(),
+            };
+            for line in lines {
+                let paragraph = self.code_paragraph(&style).add_run(code_run(line));
+                docx = self.emit_paragraph(docx, paragraph);
+            }
         }
-        docx.add_table(docx_rs::Table::new(std::mem::take(&mut self.table)))
+        docx
+    }
+
+    fn visit_table(&mut self, table: &Table, docx: Self::Output) -> Self::Output {
+        let number = self.reference.table_number(table.position.as_ref());
+        self.add_table(table, None, number, docx)
     }
 
     fn visit_table_row(
@@ -536,18 +1667,22 @@ impl MarkdownNodeTraverser for Emitter {
         mut docx: Self::Output,
         is_last_row: bool,
     ) -> Self::Output {
+        let had_metadata = self.table_metadata.is_some();
         self.table.push(docx_rs::TableRow::new(vec![]));
+        self.table_cells.push(vec![]);
         for (i, child) in row.children.iter().enumerate() {
             let last = i == (row.children.len() - 1);
             if let Node::TableCell(table_cell) = child {
+                self.table_column = i;
                 docx = self.visit_table_cell(table_cell, docx, last && is_last_row);
             } else {
                 docx = self.process_child(child, docx);
             }
         }
         // If last row of table contains metadata, remove last row
-        if self.table_metadata.is_some() {
+        if !had_metadata && self.table_metadata.is_some() {
             self.table.pop();
+            self.table_cells.pop();
         }
         docx
     }
@@ -555,38 +1690,207 @@ impl MarkdownNodeTraverser for Emitter {
     fn visit_table_cell(
         &mut self,
         cell: &mdast::TableCell,
-        mut docx: Self::Output,
+        docx: Self::Output,
         is_last_cell: bool,
     ) -> Self::Output {
-        self.paragraph = docx_rs::Paragraph::new();
-        if is_last_cell && cell.children.len() == 1 {
-            let child = cell.children.get(0).unwrap();
-            if let Node::Text(text) = child {
-                let metadata: Result<TableMetadata, serde_json::Error> =
-                    serde_json::from_str(&text.value);
-                if let Ok(metadata) = metadata {
-                    info!("Table has caption: {}", metadata.caption);
-                    self.table_metadata = Some(metadata);
-                }
-            } else {
-                debug!("Not a Text Node");
-            }
+        // A `<` cell merges into the cell to its left and a `^` cell into the
+        // cell above. Imported data is taken literally
+        let marker = if self.imported_table {
+            None
+        } else {
+            merge_marker(cell)
+        };
+        match marker {
+            Some(MERGE_LEFT) if self.merge_left() => return docx,
+            Some(MERGE_ABOVE) if self.merge_above() => return docx,
+            Some(marker) => warn!(
+                "Table cell '{}' in column {} has no cell to merge into",
+                marker,
+                self.table_column + 1
+            ),
+            None => {}
         }
-        for child in cell.children.iter() {
-            docx = self.process_node(child, docx);
+
+        if is_last_cell
+            && self.table_metadata.is_none()
+            && let Some(metadata) = TableMetadata::from_cell(cell)
+        {
+            info!("Table has metadata: {:?}", metadata);
+            self.table_metadata = Some(metadata);
         }
-        let tcell = TableCell::new();
-        let tcell = tcell.add_paragraph(std::mem::take(&mut self.paragraph));
-        self.table
-            .iter_mut()
-            .last()
-            .unwrap()
-            .cells
-            .push(TableRowChild::TableCell(tcell));
+        // Render the cell content like a body paragraph, collecting the
+        // resulting paragraphs into the cell
+        let content = Node::Paragraph(mdast::Paragraph {
+            children: cell.children.clone(),
+            position: cell.position.clone(),
+        });
+        let (paragraphs, docx) = self.render_container(ContainerKind::TableCell, &[content], docx);
+        let tcell = paragraphs
+            .into_iter()
+            .fold(self.table_cell(), |tcell, paragraph| {
+                tcell.add_paragraph(paragraph)
+            });
+        self.push_table_cell(tcell, false);
         docx
     }
 }
 
+const CODE_STYLE: &str = "SourceCode";
+const INLINE_CODE_STYLE: &str = "VerbatimChar";
+const HYPERLINK_STYLE: &str = "Hyperlink";
+const BODY_INDENT: i32 = 720;
+// docx-rs always emits its own numbering with ID 1, so ours start at 2
+const BULLET_NUMBERING_ID: usize = 2;
+const ORDERED_NUMBERING_ID: usize = 3;
+const HEADING_NUMBERING_ID: usize = 4;
+// Each ordered list gets its own numbering instance, starting from this ID
+const FIRST_LIST_NUMBERING_ID: usize = 5;
+const LIST_LEVELS: usize = 9;
+const LIST_INDENT: i32 = 720;
+const LIST_HANGING: i32 = 360;
+const CHECKBOX_FONT: &str = "MS Gothic";
+const BULLET_GLYPHS: [&str; 3] = ["•", "○", "▪"];
+// Number format and the text following the number at each level
+const NUMBER_FORMATS: [(&str, &str); 3] =
+    [("decimal", "."), ("lowerLetter", ")"), ("lowerRoman", ".")];
+const QUOTE_STYLE: &str = "Quote";
+const QUOTE_INDENT: i32 = 720;
+const FOOTNOTE_TEXT_STYLE: &str = "FootnoteText";
+const FOOTNOTE_REFERENCE_STYLE: &str = "FootnoteReference";
+const CODE_SHADING: &str = "F2F2F2";
+// Marker cells that merge into a neighbouring cell
+const MERGE_LEFT: &str = "<";
+const MERGE_ABOVE: &str = "^";
+// Width between the default page margins, in twips
+const TEXT_WIDTH: usize = 8504;
+// A table width of 100%, in fiftieths of a percent
+const FULL_WIDTH_PCT: usize = 5000;
+// Bounds on the text length used to weight a column's width
+const MIN_COLUMN_CHARS: usize = 4;
+const MAX_COLUMN_CHARS: usize = 40;
+const CAPTION_STYLE: &str = "Caption";
+const HEADING_LEVELS: u8 = 6;
+// Font sizes of heading levels 1 to 6, in half-points
+const HEADING_SIZES: [usize; 6] = [36, 28, 24, 20, 20, 20];
+const TABLE_HEADING_STYLE: &str = "TableHeading";
+const TABLE_HEADER_SHADING: &str = "D9D9D9";
+const TABLE_BAND_SHADING: &str = "F2F2F2";
+const TABLE_BORDER_SIZE: usize = 4;
+const TABLE_BORDER_COLOR: &str = "808080";
+
+fn code_fonts(font: &str) -> RunFonts {
+    RunFonts::new()
+        .ascii(font)
+        .hi_ansi(font)
+        .east_asia(font)
+        .cs(font)
+}
+
+/// Returns the merge marker if the cell holds only `<` or `^`
+fn merge_marker(cell: &mdast::TableCell) -> Option<&'static str> {
+    match cell.children.as_slice() {
+        [Node::Text(text)] => [MERGE_LEFT, MERGE_ABOVE]
+            .into_iter()
+            .find(|marker| text.value.trim() == *marker),
+        _ => None,
+    }
+}
+
+/// Weights each column by the length of its longest cell, skipping the
+/// metadata row if present
+fn content_widths(table: &Table, columns: usize, metadata_row: bool) -> Vec<f32> {
+    let mut lengths = vec![MIN_COLUMN_CHARS; columns];
+    let rows = if metadata_row {
+        &table.children[..table.children.len().saturating_sub(1)]
+    } else {
+        &table.children[..]
+    };
+    for row in rows {
+        if let Node::TableRow(row) = row {
+            for (column, cell) in row.children.iter().enumerate().take(columns) {
+                let length = cell.to_string().chars().count();
+                lengths[column] = lengths[column].max(length.min(MAX_COLUMN_CHARS));
+            }
+        }
+    }
+    lengths.into_iter().map(|length| length as f32).collect()
+}
+
+/// The paragraph style of a heading level, e.g. "Heading1"
+fn heading_style(level: u8) -> String {
+    format!("Heading{}", level.clamp(1, HEADING_LEVELS))
+}
+
+/// Builds the table borders for a border style
+fn table_borders(style: TableBorderStyle) -> TableBorders {
+    let positions = match style {
+        TableBorderStyle::Grid => vec![
+            TableBorderPosition::Top,
+            TableBorderPosition::Left,
+            TableBorderPosition::Bottom,
+            TableBorderPosition::Right,
+            TableBorderPosition::InsideH,
+            TableBorderPosition::InsideV,
+        ],
+        TableBorderStyle::Horizontal => vec![
+            TableBorderPosition::Top,
+            TableBorderPosition::Bottom,
+            TableBorderPosition::InsideH,
+        ],
+        TableBorderStyle::None => vec![],
+    };
+    positions
+        .into_iter()
+        .fold(TableBorders::with_empty(), |borders, position| {
+            borders.set(
+                TableBorder::new(position)
+                    .border_type(BorderType::Single)
+                    .size(TABLE_BORDER_SIZE)
+                    .color(TABLE_BORDER_COLOR),
+            )
+        })
+}
+
+/// The left border drawn beside block quotes
+fn quote_borders() -> ParagraphBorders {
+    ParagraphBorders::with_empty().set(
+        ParagraphBorder::new(ParagraphBorderPosition::Left)
+            .val(BorderType::Single)
+            .size(12)
+            .space(8)
+            .color("A6A6A6"),
+    )
+}
+
+/// Builds a run for a line of code, keeping leading whitespace and tabs
+fn code_run(line: &str) -> Run {
+    let mut run = Run::new();
+    for (i, segment) in line.split('\t').enumerate() {
+        if i > 0 {
+            run = run.add_tab();
+        }
+        if !segment.is_empty() {
+            run = run.add_text(segment);
+        }
+    }
+    run
+}
+
+/// Builds a run for a highlighted token, applying its color and font style
+fn highlighted_run(text: &str, style: highlighter::Style) -> Run {
+    let mut run = code_run(text).color(highlighter::hex_color(style.foreground));
+    if style.font_style.contains(highlighter::FontStyle::BOLD) {
+        run = run.bold();
+    }
+    if style.font_style.contains(highlighter::FontStyle::ITALIC) {
+        run = run.italic();
+    }
+    if style.font_style.contains(highlighter::FontStyle::UNDERLINE) {
+        run = run.underline("single");
+    }
+    run
+}
+
 /// Returns the image dimensions in (EMU, EMU)
 fn get_image_dimensions(file_path: &PathBuf) -> Result<(u32, u32)> {
     let reader = image::io::Reader::open(file_path)?;
@@ -594,6 +1898,8 @@ fn get_image_dimensions(file_path: &PathBuf) -> Result<(u32, u32)> {
     Ok((EMUS_PER_INCH * dim1 / PPI, EMUS_PER_INCH * dim2 / PPI))
 }
 
+static LINE_BREAK_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(?i)<br\s*/?>$").unwrap());
+
 static REF_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\{ref:\s*([^}]*)\s*}"#).unwrap());
 
 fn extract_ref(text: &str) -> Option<&str> {
@@ -601,3 +1907,162 @@ fn extract_ref(text: &str) -> Option<&str> {
         .captures(text)
         .and_then(|caps| caps.get(1).map(|m| m.as_str()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn convert(markdown: &str) -> Docx {
+        Parser::new(markdown, None).parse_to_docx()
+    }
+
+    /// The cells of the first table, row by row, as their text followed by
+    /// their grid span and vertical merge, e.g. `Model:2:restart`
+    fn table_cells(markdown: &str) -> Vec<Vec<String>> {
+        let docx = convert(markdown);
+        let Some(DocumentChild::Table(table)) = docx
+            .document
+            .children
+            .iter()
+            .find(|child| matches!(child, DocumentChild::Table(_)))
+        else {
+            panic!("no table in {:?}", markdown);
+        };
+        table
+            .rows
+            .iter()
+            .map(|TableChild::TableRow(row)| {
+                row.cells
+                    .iter()
+                    .map(|TableRowChild::TableCell(cell)| {
+                        // Runs hold their text escaped for XML
+                        let text: String = cell
+                            .children
+                            .iter()
+                            .filter_map(|content| match content {
+                                TableCellContent::Paragraph(paragraph) => {
+                                    Some(paragraph.raw_text().replace("&lt;", "<"))
+                                }
+                                _ => None,
+                            })
+                            .collect();
+                        let property = serde_json::to_value(&cell.property).unwrap();
+                        let mut description = text;
+                        if let Some(span) = property["gridSpan"].as_u64() {
+                            description += &format!(":{}", span);
+                        }
+                        if let Some(merge) = property["verticalMerge"].as_str() {
+                            description += &format!(":{}", merge);
+                        }
+                        description
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The text and numbering instance of each numbered paragraph
+    fn numbered_paragraphs(docx: &Docx) -> Vec<(String, usize)> {
+        docx.document
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                DocumentChild::Paragraph(paragraph) => {
+                    let numbering = paragraph.property.numbering_property.as_ref()?;
+                    Some((paragraph.raw_text(), numbering.id.as_ref()?.id))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The numbering definition and the level start overrides of an instance
+    fn numbering_instance(docx: &Docx, id: usize) -> (usize, Vec<(usize, Option<usize>)>) {
+        let numbering = docx
+            .numberings
+            .numberings
+            .iter()
+            .find(|numbering| numbering.id == id)
+            .unwrap();
+        let overrides = numbering
+            .level_overrides
+            .iter()
+            .map(|level| (level.level, level.override_start))
+            .collect();
+        (numbering.abstract_num_id, overrides)
+    }
+
+    #[test]
+    fn each_ordered_list_restarts_its_numbering() {
+        let docx = convert("1. one\n2. two\n\ntext\n\n5. five\n6. six\n   1. nested\n\n- bullet\n");
+        let paragraphs = numbered_paragraphs(&docx);
+        let ids: Vec<(&str, usize)> = paragraphs
+            .iter()
+            .map(|(text, id)| (text.as_str(), *id))
+            .collect();
+        let first = FIRST_LIST_NUMBERING_ID;
+        assert_eq!(
+            ids,
+            [
+                ("one", first),
+                ("two", first),
+                ("five", first + 1),
+                ("six", first + 1),
+                ("nested", first + 2),
+                ("bullet", BULLET_NUMBERING_ID),
+            ]
+        );
+        assert_eq!(
+            numbering_instance(&docx, first),
+            (ORDERED_NUMBERING_ID, vec![(0, Some(1))])
+        );
+        assert_eq!(
+            numbering_instance(&docx, first + 1),
+            (ORDERED_NUMBERING_ID, vec![(0, Some(5))])
+        );
+        assert_eq!(
+            numbering_instance(&docx, first + 2),
+            (ORDERED_NUMBERING_ID, vec![(1, Some(1))])
+        );
+    }
+
+    #[test]
+    fn merged_cells_continue_a_spanned_cell() {
+        let cells = table_cells(
+            "| Model | Accuracy | < | Notes |\n\
+             |-------|----------|---|-------|\n\
+             | ^     | ^        | < | ^     |\n\
+             | Base  | 71.2     | < | -     |\n",
+        );
+        assert_eq!(
+            cells,
+            [
+                ["Model:restart", "Accuracy:2:restart", "Notes:restart"],
+                [":continue", ":2:continue", ":continue"],
+                ["Base", "71.2:2", "-"],
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_above_needs_a_cell_starting_at_its_column() {
+        // The `^` in the last column sits under the second half of a span
+        let cells = table_cells(
+            "| A | B | < |\n\
+             |---|---|---|\n\
+             | x | y | ^ |\n",
+        );
+        assert_eq!(cells, [vec!["A", "B:2"], vec!["x", "y", "^"]]);
+    }
+
+    #[test]
+    fn merge_markers_without_a_target_are_kept_as_text() {
+        let cells = table_cells(
+            "| ^ | B |\n\
+             |---|---|\n\
+             | < | y |\n",
+        );
+        assert_eq!(cells, [["^", "B"], ["<", "y"]]);
+    }
+}
@@ -0,0 +1,68 @@
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+pub use syntect::highlighting::{Color, FontStyle, Style, Theme};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+static THEME_SET: Lazy<ThemeSet> = Lazy::new(ThemeSet::load_defaults);
+
+/// A highlighted line of code, as a sequence of styled tokens
+pub type HighlightedLine = Vec<(Style, String)>;
+
+/// Looks up a theme bundled with the highlighter by name
+pub fn find_theme(name: &str) -> Option<&'static Theme> {
+    let theme = THEME_SET.themes.get(name);
+    if theme.is_none() {
+        let mut available: Vec<&String> = THEME_SET.themes.keys().collect();
+        available.sort();
+        warn!(
+            "Unknown code theme '{}', available themes: {:?}",
+            name, available
+        );
+    }
+    theme
+}
+
+fn find_syntax(lang: &str) -> Option<&'static SyntaxReference> {
+    // Common fence names that do not match a syntax name or file extension
+    let token = match lang.to_ascii_lowercase().as_str() {
+        "shell" | "console" | "zsh" | "sh" => String::from("bash"),
+        other => String::from(other),
+    };
+    SYNTAX_SET.find_syntax_by_token(&token)
+}
+
+/// Highlights `code` written in `lang`, returning `None` if the language is unknown
+pub fn highlight(code: &str, lang: &str, theme: &Theme) -> Option<Vec<HighlightedLine>> {
+    let Some(syntax) = find_syntax(lang) else {
+        debug!("No syntax definition for language '{}'", lang);
+        return None;
+    };
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(code) {
+        match highlighter.highlight_line(line, &SYNTAX_SET) {
+            Ok(tokens) => lines.push(
+                tokens
+                    .into_iter()
+                    .map(|(style, text)| (style, text.trim_end_matches(['\r', '\n']).to_string()))
+                    .filter(|(_, text)| !text.is_empty())
+                    .collect(),
+            ),
+            Err(e) => {
+                warn!("Failed to highlight {} code: {}", lang, e);
+                return None;
+            }
+        }
+    }
+    Some(lines)
+}
+
+/// Formats a highlighter color as a hex string suitable for DOCX
+pub fn hex_color(color: Color) -> String {
+    format!("{:02X}{:02X}{:02X}", color.r, color.g, color.b)
+}
@@ -1,8 +1,15 @@
 use log::{debug, error, info};
+use markdown::mdast::Node;
+use markdown::unist::Position;
 use serde::Deserialize;
 
-use crate::{metadata::TableMetadata, traverser::MarkdownNodeTraverser};
+use crate::{
+    csv_table,
+    metadata::{ReferenceKind, TableMetadata, bookmark_name, split_heading_attributes},
+    traverser::MarkdownNodeTraverser,
+};
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct ImageModifiers {
@@ -19,28 +26,143 @@ impl Default for ImageModifiers {
     }
 }
 
+/// A labeled heading that `{ref: ...}` can point to
+#[derive(Debug, Clone)]
+pub struct SectionReference {
+    /// The section number, e.g. "2.3", or `None` for unnumbered headings
+    pub number: Option<String>,
+    /// The heading text
+    pub text: String,
+    /// The source offset of the heading
+    pub offset: usize,
+}
+
+/// Numbers captioned figures and tables and collects the labels that
+/// `{ref: ...}` can point to. Numbers are keyed by the source offset of the
+/// image, table or `csv` block so that the emitter captions exactly the
+/// nodes counted here
 #[derive(Default, Debug, Clone)]
 pub struct ImageReferenceCollector {
     image_count: usize,
     image_references: HashMap<String, usize>,
+    figure_numbers: HashMap<usize, usize>,
     table_count: usize,
     table_references: HashMap<String, usize>,
+    table_numbers: HashMap<usize, usize>,
+    section_numbers: Vec<usize>,
+    section_references: HashMap<String, SectionReference>,
+    bookmarks: HashMap<String, String>,
+    in_table: bool,
 }
 
-impl Into<HashMap<String, usize>> for ImageReferenceCollector {
-    fn into(self) -> HashMap<String, usize> {
-        self.image_references
+impl From<ImageReferenceCollector> for HashMap<String, usize> {
+    fn from(value: ImageReferenceCollector) -> Self {
+        value.image_references
     }
 }
 
 impl ImageReferenceCollector {
-    pub fn get(&self, r#ref: &str) -> Option<String> {
+    /// The kind and number of the figure or table labeled `ref`
+    pub fn get(&self, r#ref: &str) -> Option<(ReferenceKind, usize)> {
         if let Some(n) = self.image_references.get(r#ref) {
-            Some(format!("Figure {}", *n))
-        } else if let Some(n) = self.table_references.get(r#ref) {
-            Some(format!("Table {}", *n))
+            Some((ReferenceKind::Figure, *n))
         } else {
-            None
+            self.table_references
+                .get(r#ref)
+                .map(|n| (ReferenceKind::Table, *n))
+        }
+    }
+
+    /// The number of the captioned figure at `position`, if it has a caption
+    pub fn figure_number(&self, position: Option<&Position>) -> Option<usize> {
+        self.figure_numbers.get(&position?.start.offset).copied()
+    }
+
+    /// The number of the captioned table at `position`, if it has a caption
+    pub fn table_number(&self, position: Option<&Position>) -> Option<usize> {
+        self.table_numbers.get(&position?.start.offset).copied()
+    }
+
+    /// Whether a figure, table or section is labeled `ref`
+    pub fn is_defined(&self, r#ref: &str) -> bool {
+        self.bookmarks
+            .get(&bookmark_name(r#ref))
+            .is_some_and(|label| label == r#ref)
+    }
+
+    /// The section labeled `ref`
+    pub fn get_section(&self, r#ref: &str) -> Option<&SectionReference> {
+        self.section_references.get(r#ref)
+    }
+
+    // Claim a label for a figure, table or section. Labels share one
+    // namespace, and no two labels may have the same bookmark name
+    fn define(&mut self, label: &str) -> bool {
+        match self.bookmarks.entry(bookmark_name(label)) {
+            Entry::Occupied(entry) if entry.get() == label => {
+                error!("Multiple defined reference: {}", label);
+                false
+            }
+            Entry::Occupied(entry) => {
+                error!(
+                    "Reference '{}' has the same bookmark name as '{}'",
+                    label,
+                    entry.get()
+                );
+                false
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(label.to_string());
+                true
+            }
+        }
+    }
+
+    // Number a captioned table, as the SEQ field in its caption does
+    fn collect_table(&mut self, metadata: TableMetadata, position: Option<&Position>) {
+        let Some(position) = position else {
+            return;
+        };
+        if metadata.caption.is_none() {
+            return;
+        }
+        self.table_count += 1;
+        self.table_numbers
+            .insert(position.start.offset, self.table_count);
+        if let Some(reference) = metadata.r#ref
+            && self.define(&reference)
+        {
+            info!(
+                "Adding table reference: {} -> {}",
+                reference, self.table_count
+            );
+            self.table_references.insert(reference, self.table_count);
+        }
+    }
+
+    // Number every captioned figure in document order, as the SEQ fields in
+    // the captions do
+    fn collect_image(&mut self, alt: &str, position: Option<&Position>) {
+        let Some(position) = position else {
+            return;
+        };
+        // Check if the image has a reference ID in its alt text
+        let res: ImageModifiers = serde_json::from_str(alt).unwrap_or(ImageModifiers::default());
+
+        // Images in table cells are only captioned if they can be referenced
+        if self.in_table && res.r#ref.is_none() {
+            return;
+        }
+
+        self.image_count += 1;
+        self.figure_numbers
+            .insert(position.start.offset, self.image_count);
+        if let Some(reference) = res.r#ref
+            && self.define(&reference)
+        {
+            let figure_number = self.image_count;
+            info!("Adding image reference: {} -> {}", reference, figure_number);
+            self.image_references.insert(reference, figure_number);
         }
     }
 }
@@ -48,58 +170,108 @@ impl ImageReferenceCollector {
 impl MarkdownNodeTraverser for ImageReferenceCollector {
     type Output = ();
 
+    fn visit_heading(
+        &mut self,
+        heading: &markdown::mdast::Heading,
+        _result: Self::Output,
+    ) -> Self::Output {
+        let (children, attributes) = split_heading_attributes(&heading.children);
+
+        // Count sections like the heading numbering does, restarting the
+        // numbers of deeper levels below each heading
+        let level = heading.depth as usize;
+        let number = if attributes.unnumbered {
+            None
+        } else {
+            self.section_numbers.resize(level, 0);
+            self.section_numbers[level - 1] += 1;
+            Some(
+                self.section_numbers
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<_>>()
+                    .join("."),
+            )
+        };
+
+        if let Some(id) = attributes.id
+            && let Some(position) = &heading.position
+            && self.define(&id)
+        {
+            let text = children.iter().map(|child| child.to_string()).collect();
+            info!("Adding section reference: {} -> {:?}", id, number);
+            self.section_references.insert(
+                id,
+                SectionReference {
+                    number,
+                    text,
+                    offset: position.start.offset,
+                },
+            );
+        }
+    }
+
     fn visit_image(
         &mut self,
         image: &markdown::mdast::Image,
-        mut _result: Self::Output,
+        _result: Self::Output,
     ) -> Self::Output {
         debug!(
             "First pass - collecting image reference: url={}, alt={}",
             image.url, image.alt
         );
+        self.collect_image(&image.alt, image.position.as_ref());
+    }
 
-        // Check if the image has a reference ID in its alt text
-        let res: ImageModifiers =
-            serde_json::from_str(&image.alt).unwrap_or(ImageModifiers::default());
+    fn visit_image_reference(
+        &mut self,
+        img_ref: &markdown::mdast::ImageReference,
+        _result: Self::Output,
+    ) -> Self::Output {
+        debug!(
+            "First pass - collecting image reference: identifier={}, alt={}",
+            img_ref.identifier, img_ref.alt
+        );
+        self.collect_image(&img_ref.alt, img_ref.position.as_ref());
+    }
 
-        if let Some(reference) = res.r#ref {
-            self.image_count += 1;
-            let figure_number = self.image_count;
+    fn visit_link_reference(
+        &mut self,
+        link_ref: &markdown::mdast::LinkReference,
+        mut result: Self::Output,
+    ) -> Self::Output {
+        // Linked images are captioned like other images
+        for child in link_ref.children.iter() {
+            result = self.process_node(child, result);
+        }
+        result
+    }
 
-            match self.image_references.get(&reference) {
-                Some(_) => {
-                    error!("Multiple defined reference: {}", reference);
-                }
-                None => {
-                    info!("Adding image reference: {} -> {}", reference, figure_number);
-                    self.image_references.insert(reference, figure_number);
-                }
-            }
+    fn visit_code(&mut self, code: &markdown::mdast::Code, _result: Self::Output) -> Self::Output {
+        // Tables imported from CSV/TSV blocks are numbered with the others
+        if csv_table::delimiter(code).is_some()
+            && let Some(Ok(options)) = csv_table::options(code)
+            && options.is_table()
+        {
+            self.collect_table(options.table_metadata(), code.position.as_ref());
         }
-        ()
     }
 
     fn visit_table(
         &mut self,
         table: &markdown::mdast::Table,
-        result: Self::Output,
+        mut result: Self::Output,
     ) -> Self::Output {
-        self.table_count += 1;
         for row in table.children.iter() {
-            if let markdown::mdast::Node::TableRow(row) = row {
+            if let Node::TableRow(row) = row {
                 for cell in row.children.iter() {
-                    if let markdown::mdast::Node::TableCell(cell) = cell {
-                        if cell.children.is_empty() {
-                            continue;
-                        }
-                        if let markdown::mdast::Node::Text(text) = cell.children.get(0).unwrap() {
-                            let metadata: Result<TableMetadata, serde_json::Error> =
-                                serde_json::from_str(&text.value);
-                            if let Ok(metadata) = metadata {
-                                self.table_references
-                                    .insert(metadata.r#ref, self.table_count);
-                            }
+                    if let Node::TableCell(cell) = cell {
+                        // Collect images placed in the cell
+                        self.in_table = true;
+                        for child in cell.children.iter() {
+                            result = self.process_node(child, result);
                         }
+                        self.in_table = false;
                     } else {
                         error!("Unexpected Node Type in TableRow");
                     }
@@ -108,6 +280,52 @@ impl MarkdownNodeTraverser for ImageReferenceCollector {
                 error!("Unexpected Node Type in Table");
             }
         }
+        // The metadata is in the last cell of the last row, as the emitter
+        // expects it
+        if let Some(Node::TableRow(row)) = table.children.last()
+            && let Some(Node::TableCell(cell)) = row.children.last()
+            && let Some(metadata) = TableMetadata::from_cell(cell)
+        {
+            self.collect_table(metadata, table.position.as_ref());
+        }
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(markdown: &str) -> ImageReferenceCollector {
+        let ast = markdown::to_mdast(markdown, &markdown::ParseOptions::gfm()).unwrap();
+        let mut collector = ImageReferenceCollector::default();
+        collector.process_node(&ast, ());
+        collector
+    }
+
+    #[test]
+    fn unnumbered_headings_leave_section_numbers_alone() {
+        let collector = collect(
+            "# Introduction {#sec:intro}\n\n\
+             ## Scope {#sec:scope}\n\n\
+             # Preface {- #sec:preface}\n\n\
+             ## Detail {#sec:detail}\n\n\
+             # Methods {#sec:methods}\n\n\
+             ## Data {#sec:data}\n\n\
+             # Appendix {.unnumbered #sec:appendix}\n\n\
+             ## Tables {#sec:tables}\n",
+        );
+        let section = |id: &str| {
+            let section = collector.get_section(id).unwrap();
+            (section.number.as_deref(), section.text.as_str())
+        };
+        assert_eq!(section("sec:intro"), (Some("1"), "Introduction"));
+        assert_eq!(section("sec:scope"), (Some("1.1"), "Scope"));
+        assert_eq!(section("sec:preface"), (None, "Preface"));
+        assert_eq!(section("sec:detail"), (Some("1.2"), "Detail"));
+        assert_eq!(section("sec:methods"), (Some("2"), "Methods"));
+        assert_eq!(section("sec:data"), (Some("2.1"), "Data"));
+        assert_eq!(section("sec:appendix"), (None, "Appendix"));
+        assert_eq!(section("sec:tables"), (Some("2.2"), "Tables"));
+    }
+}
@@ -2,11 +2,17 @@ use docx_rs::*;
 use log::{error, info};
 use std::path::PathBuf;
 
+mod csv_table;
+mod definition_collector;
 mod emitter;
+mod highlighter;
 mod image_reference_collector;
 mod metadata;
+mod options;
 mod parser;
+mod postprocessor;
 mod traverser;
+use options::Options;
 use parser::Parser;
 
 const SIMPLE_MARKDOWN_YFM: &str = r#"
@@ -47,6 +53,9 @@ struct Cli {
     /// Trace-level logging (includes all debug info plus markdown content)
     #[arg(long)]
     trace: bool,
+
+    #[command(flatten)]
+    options: Options,
 }
 
 fn main() -> Result<(), DocxError> {
@@ -93,6 +102,7 @@ fn main() -> Result<(), DocxError> {
         .as_ref()
         .and_then(|path| path.parent().map(|p| p.to_path_buf()));
     let mut parser = Parser::new(&markdown_content, base_path);
+    parser.set_options(cli.options);
 
     // Determine the output filename
     let output_path = if let Some(output) = cli.output {
@@ -118,7 +128,7 @@ fn main() -> Result<(), DocxError> {
 
     // Parse markdown and generate DOCX
     let docx = parser.parse_to_docx();
-    match docx.build().pack(file) {
+    match parser.build(docx).pack(file) {
         Ok(_) => {
             info!(
                 "Successfully created DOCX file at: {}",
@@ -1,22 +1,124 @@
+use markdown::mdast::{Node, TableCell};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::Deserialize;
 
-use crate::image_reference_collector::ImageReferenceCollector;
-
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct TableMetadata {
-    pub caption: String,
-    pub r#ref: String,
+    pub caption: Option<String>,
+    pub r#ref: Option<String>,
+    /// Relative column widths, one per column
+    pub widths: Option<Vec<f32>>,
+    /// Let Word resize columns to fit their content (default: true)
+    pub autofit: Option<bool>,
 }
 
 impl TableMetadata {
-    pub fn to_string(&self, imc: &ImageReferenceCollector) -> String {
-        match imc.get(&self.r#ref) {
-            Some(n) => format!("{}: {}", n, self.caption),
-            None => format!("Table ??: {}", self.caption),
+    /// Parses the metadata in a table cell holding only its JSON. A cell
+    /// setting no field, such as `{}`, is table content
+    pub fn from_cell(cell: &TableCell) -> Option<TableMetadata> {
+        match cell.children.as_slice() {
+            [Node::Text(text)] => serde_json::from_str(&text.value)
+                .ok()
+                .filter(|metadata: &TableMetadata| !metadata.is_empty()),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.caption.is_none()
+            && self.r#ref.is_none()
+            && self.widths.is_none()
+            && self.autofit.is_none()
+    }
+}
+
+/// The kind of numbered object that a `{ref: ...}` label points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceKind {
+    Figure,
+    Table,
+}
+
+/// Attributes in braces at the end of a heading, in the style of Pandoc,
+/// e.g. `# Methods {#sec:methods}` or `# Background {-}`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeadingAttributes {
+    /// The label that `{ref: ...}` uses to refer to the section
+    pub id: Option<String>,
+    /// Excluded from section numbering by `{-}` or `{.unnumbered}`
+    pub unnumbered: bool,
+}
+
+static HEADING_ATTRIBUTES_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\s*\{([^{}]*)\}\s*$").unwrap());
+
+impl HeadingAttributes {
+    /// Splits trailing attributes off heading text. Returns `None` unless every
+    /// word in the braces is an attribute (`-`, `.class` or `#id`)
+    pub fn parse(text: &str) -> Option<(&str, HeadingAttributes)> {
+        let captures = HEADING_ATTRIBUTES_REGEX.captures(text)?;
+        let mut attributes = HeadingAttributes::default();
+        let mut words = captures[1].split_whitespace().peekable();
+        words.peek()?;
+        for word in words {
+            match word {
+                "-" | ".unnumbered" => attributes.unnumbered = true,
+                _ if word.len() > 1 && word.starts_with('#') => {
+                    attributes.id = Some(word[1..].to_string())
+                }
+                _ if word.len() > 1 && word.starts_with('.') => {}
+                _ => return None,
+            }
         }
+        Some((&text[..captures.get(0).unwrap().start()], attributes))
     }
 }
 
+/// Removes trailing attributes from the last text of a heading, returning the
+/// remaining content and the attributes
+pub fn split_heading_attributes(children: &[Node]) -> (Vec<Node>, HeadingAttributes) {
+    let mut children = children.to_vec();
+    if let Some(Node::Text(text)) = children.last_mut()
+        && let Some((stripped, attributes)) = HeadingAttributes::parse(&text.value)
+    {
+        text.value = stripped.to_string();
+        return (children, attributes);
+    }
+    (children, HeadingAttributes::default())
+}
+
+const BOOKMARK_PREFIX: &str = "_Ref";
+const MAX_BOOKMARK_LENGTH: usize = 40;
+const BOOKMARK_HASH_LENGTH: usize = 8;
+
+/// The name of the bookmark for a label. Names starting with "_Ref" are
+/// hidden in Word, and names may only hold letters, digits and underscores
+/// and are at most 40 characters long. A hash of the whole label keeps the
+/// names of labels that differ only in replaced or truncated characters apart
+pub fn bookmark_name(label: &str) -> String {
+    let readable = MAX_BOOKMARK_LENGTH - BOOKMARK_PREFIX.len() - BOOKMARK_HASH_LENGTH - 1;
+    let mut name: String = BOOKMARK_PREFIX
+        .chars()
+        .chain(
+            label
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .take(readable),
+        )
+        .collect();
+    name.push_str(&format!("_{:08x}", fnv1a(label)));
+    name
+}
+
+// 32-bit FNV-1a, a stable hash for bookmark names
+fn fnv1a(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct StackCounter {
     value_: u32,
@@ -41,7 +143,8 @@ impl StackCounter {
 
 #[derive(Debug, Clone, Copy)]
 pub enum ListType {
-    Ordered,
+    /// An ordered list with its own numbering instance
+    Ordered(usize),
     Unordered,
 }
 
@@ -56,3 +159,72 @@ impl Default for StackCounter {
         StackCounter::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use markdown::mdast::Text;
+
+    fn cell(value: &str) -> TableCell {
+        TableCell {
+            children: vec![Node::Text(Text {
+                value: value.to_string(),
+                position: None,
+            })],
+            position: None,
+        }
+    }
+
+    #[test]
+    fn table_metadata_needs_a_field() {
+        assert!(TableMetadata::from_cell(&cell("{}")).is_none());
+        assert!(TableMetadata::from_cell(&cell(r#"{"caption": null}"#)).is_none());
+        assert!(TableMetadata::from_cell(&cell(r#"{"colour": "red"}"#)).is_none());
+        assert!(TableMetadata::from_cell(&cell("Earth")).is_none());
+
+        let metadata = TableMetadata::from_cell(&cell(r#"{"autofit": false}"#)).unwrap();
+        assert_eq!(metadata.autofit, Some(false));
+        assert!(metadata.caption.is_none());
+
+        let metadata =
+            TableMetadata::from_cell(&cell(r#"{"ref": "planets", "caption": "The Planets"}"#))
+                .unwrap();
+        assert_eq!(metadata.r#ref.as_deref(), Some("planets"));
+        assert_eq!(metadata.caption.as_deref(), Some("The Planets"));
+    }
+
+    #[test]
+    fn heading_attributes_mark_unnumbered_headings() {
+        let (text, attributes) = HeadingAttributes::parse("Acknowledgements {-}").unwrap();
+        assert_eq!(text, "Acknowledgements");
+        assert_eq!(
+            attributes,
+            HeadingAttributes {
+                id: None,
+                unnumbered: true
+            }
+        );
+    }
+
+    #[test]
+    fn heading_attributes_combine_id_and_classes() {
+        let (text, attributes) =
+            HeadingAttributes::parse("Appendix  { #sec:appendix .unnumbered }").unwrap();
+        assert_eq!(text, "Appendix");
+        assert_eq!(attributes.id.as_deref(), Some("sec:appendix"));
+        assert!(attributes.unnumbered);
+
+        let (_, attributes) = HeadingAttributes::parse("Methods {#sec:methods .wide}").unwrap();
+        assert_eq!(attributes.id.as_deref(), Some("sec:methods"));
+        assert!(!attributes.unnumbered);
+    }
+
+    #[test]
+    fn heading_attributes_reject_other_braces() {
+        assert_eq!(HeadingAttributes::parse("Sets {a, b}"), None);
+        assert_eq!(HeadingAttributes::parse("Empty {}"), None);
+        assert_eq!(HeadingAttributes::parse("Bare {#}"), None);
+        assert_eq!(HeadingAttributes::parse("{-} in front"), None);
+        assert_eq!(HeadingAttributes::parse("No braces"), None);
+    }
+}
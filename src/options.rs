@@ -0,0 +1,252 @@
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+
+use crate::metadata::ReferenceKind;
+
+pub const DEFAULT_CODE_THEME: &str = "base16-ocean.light";
+pub const DEFAULT_CODE_FONT: &str = "Consolas";
+pub const DEFAULT_CAPTION_SEPARATOR: &str = ": ";
+
+/// How thematic breaks (`---`) are rendered
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThematicBreakStyle {
+    /// A horizontal rule
+    #[default]
+    Rule,
+    /// A page break
+    Page,
+}
+
+/// How task list checkboxes are rendered
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckboxStyle {
+    /// A ☒/☐ glyph in place of the bullet
+    #[default]
+    Glyph,
+    /// A clickable Word checkbox content control
+    Control,
+}
+
+/// Which borders are drawn around and inside tables
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TableBorderStyle {
+    /// Borders around every cell
+    #[default]
+    Grid,
+    /// Horizontal rules only, between rows and above and below the table
+    Horizontal,
+    /// No borders
+    None,
+}
+
+/// Where a caption is placed relative to its figure or table
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptionPosition {
+    Above,
+    Below,
+}
+
+/// How the captions of one kind of object are laid out
+#[derive(Debug, Clone, Copy)]
+pub struct CaptionFormat<'a> {
+    pub position: CaptionPosition,
+    /// The label preceding the number, e.g. "Figure"
+    pub label: &'a str,
+    /// The text between the number and the caption
+    pub separator: &'a str,
+    /// Whether the label and number are bold
+    pub label_bold: bool,
+}
+
+/// Rendering options, set in the YAML front matter or on the command line.
+/// Command line values take precedence over the front matter.
+#[derive(Args, Deserialize, Debug, Clone, Default)]
+pub struct Options {
+    /// Syntax highlighting theme for code blocks ("none" disables highlighting)
+    #[arg(long, value_name = "THEME")]
+    pub code_theme: Option<String>,
+
+    /// Monospace font for code blocks and inline code
+    #[arg(long, value_name = "FONT")]
+    pub code_font: Option<String>,
+
+    /// Shade the background of inline code spans (default: true)
+    #[arg(long, value_name = "BOOL")]
+    pub inline_code_shading: Option<bool>,
+
+    /// Italicize block quotes (default: true)
+    #[arg(long, value_name = "BOOL")]
+    pub quote_italic: Option<bool>,
+
+    /// Render thematic breaks (---) as horizontal rules or page breaks
+    #[arg(long, value_enum, value_name = "STYLE")]
+    pub thematic_break: Option<ThematicBreakStyle>,
+
+    /// Render task list checkboxes as glyphs or Word checkbox controls
+    #[arg(long, value_enum, value_name = "STYLE")]
+    pub checkbox: Option<CheckboxStyle>,
+
+    /// Borders drawn around and inside tables
+    #[arg(long, value_enum, value_name = "STYLE")]
+    pub table_borders: Option<TableBorderStyle>,
+
+    /// Shade every other body row of tables (default: false)
+    #[arg(long, value_name = "BOOL")]
+    pub table_banding: Option<bool>,
+
+    /// Place figure captions above or below the figure (default: below)
+    #[arg(long, value_enum, value_name = "POSITION")]
+    pub figure_caption_position: Option<CaptionPosition>,
+
+    /// Label preceding figure numbers (default: "Figure")
+    #[arg(long, value_name = "LABEL")]
+    pub figure_label: Option<String>,
+
+    /// Text between a figure number and its caption (default: ": ")
+    #[arg(long, value_name = "TEXT")]
+    pub figure_separator: Option<String>,
+
+    /// Bold the label and number of figure captions (default: false)
+    #[arg(long, value_name = "BOOL")]
+    pub figure_label_bold: Option<bool>,
+
+    /// Place table captions above or below the table (default: above)
+    #[arg(long, value_enum, value_name = "POSITION")]
+    pub table_caption_position: Option<CaptionPosition>,
+
+    /// Label preceding table numbers (default: "Table")
+    #[arg(long, value_name = "LABEL")]
+    pub table_label: Option<String>,
+
+    /// Text between a table number and its caption (default: ": ")
+    #[arg(long, value_name = "TEXT")]
+    pub table_separator: Option<String>,
+
+    /// Bold the label and number of table captions (default: false)
+    #[arg(long, value_name = "BOOL")]
+    pub table_label_bold: Option<bool>,
+
+    /// Number headings hierarchically, e.g. "2.3.1" (default: false)
+    #[arg(long, value_name = "BOOL")]
+    pub number_sections: Option<bool>,
+
+    /// Label preceding section numbers in references (default: "Section")
+    #[arg(long, value_name = "LABEL")]
+    pub section_label: Option<String>,
+}
+
+impl Options {
+    /// Returns these options with any unset field taken from `fallback`
+    pub fn or(self, fallback: Options) -> Options {
+        Options {
+            code_theme: self.code_theme.or(fallback.code_theme),
+            code_font: self.code_font.or(fallback.code_font),
+            inline_code_shading: self.inline_code_shading.or(fallback.inline_code_shading),
+            quote_italic: self.quote_italic.or(fallback.quote_italic),
+            thematic_break: self.thematic_break.or(fallback.thematic_break),
+            checkbox: self.checkbox.or(fallback.checkbox),
+            table_borders: self.table_borders.or(fallback.table_borders),
+            table_banding: self.table_banding.or(fallback.table_banding),
+            figure_caption_position: self
+                .figure_caption_position
+                .or(fallback.figure_caption_position),
+            figure_label: self.figure_label.or(fallback.figure_label),
+            figure_separator: self.figure_separator.or(fallback.figure_separator),
+            figure_label_bold: self.figure_label_bold.or(fallback.figure_label_bold),
+            table_caption_position: self
+                .table_caption_position
+                .or(fallback.table_caption_position),
+            table_label: self.table_label.or(fallback.table_label),
+            table_separator: self.table_separator.or(fallback.table_separator),
+            table_label_bold: self.table_label_bold.or(fallback.table_label_bold),
+            number_sections: self.number_sections.or(fallback.number_sections),
+            section_label: self.section_label.or(fallback.section_label),
+        }
+    }
+
+    /// The syntax highlighting theme, or `None` if highlighting is disabled
+    pub fn code_theme(&self) -> Option<&str> {
+        match self.code_theme.as_deref() {
+            Some("none") => None,
+            Some(theme) => Some(theme),
+            None => Some(DEFAULT_CODE_THEME),
+        }
+    }
+
+    /// The monospace font used for code
+    pub fn code_font(&self) -> &str {
+        self.code_font.as_deref().unwrap_or(DEFAULT_CODE_FONT)
+    }
+
+    /// Whether inline code spans are shaded
+    pub fn inline_code_shading(&self) -> bool {
+        self.inline_code_shading.unwrap_or(true)
+    }
+
+    /// Whether block quotes are italicized
+    pub fn quote_italic(&self) -> bool {
+        self.quote_italic.unwrap_or(true)
+    }
+
+    /// How thematic breaks are rendered
+    pub fn thematic_break(&self) -> ThematicBreakStyle {
+        self.thematic_break.unwrap_or_default()
+    }
+
+    /// How task list checkboxes are rendered
+    pub fn checkbox(&self) -> CheckboxStyle {
+        self.checkbox.unwrap_or_default()
+    }
+
+    /// Which table borders are drawn
+    pub fn table_borders(&self) -> TableBorderStyle {
+        self.table_borders.unwrap_or_default()
+    }
+
+    /// Whether every other body row of tables is shaded
+    pub fn table_banding(&self) -> bool {
+        self.table_banding.unwrap_or(false)
+    }
+
+    /// Whether headings are numbered
+    pub fn number_sections(&self) -> bool {
+        self.number_sections.unwrap_or(false)
+    }
+
+    /// The label preceding section numbers in references
+    pub fn section_label(&self) -> &str {
+        self.section_label.as_deref().unwrap_or("Section")
+    }
+
+    /// How captions of figures or tables are laid out
+    pub fn caption_format(&self, kind: ReferenceKind) -> CaptionFormat<'_> {
+        match kind {
+            ReferenceKind::Figure => CaptionFormat {
+                position: self
+                    .figure_caption_position
+                    .unwrap_or(CaptionPosition::Below),
+                label: self.figure_label.as_deref().unwrap_or("Figure"),
+                separator: self
+                    .figure_separator
+                    .as_deref()
+                    .unwrap_or(DEFAULT_CAPTION_SEPARATOR),
+                label_bold: self.figure_label_bold.unwrap_or(false),
+            },
+            ReferenceKind::Table => CaptionFormat {
+                position: self
+                    .table_caption_position
+                    .unwrap_or(CaptionPosition::Above),
+                label: self.table_label.as_deref().unwrap_or("Table"),
+                separator: self
+                    .table_separator
+                    .as_deref()
+                    .unwrap_or(DEFAULT_CAPTION_SEPARATOR),
+                label_bold: self.table_label_bold.unwrap_or(false),
+            },
+        }
+    }
+}
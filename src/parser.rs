@@ -1,13 +1,16 @@
 use std::path::PathBuf;
 
 use docx_rs::*;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use markdown::to_mdast;
 use serde::Deserialize;
+use serde::de::IgnoredAny;
 use yaml_front_matter::YamlFrontMatter;
 
+use crate::definition_collector::DefinitionCollector;
 use crate::emitter::Emitter;
 use crate::image_reference_collector::ImageReferenceCollector;
+use crate::options::Options;
 use crate::traverser::MarkdownNodeTraverser;
 
 pub const PPI: u32 = 220;
@@ -23,27 +26,52 @@ pub struct Metadata {
 #[derive(Default, Debug, Clone)]
 pub struct Parser {
     metadata: Option<Metadata>,
+    options: Options,
     content: String,
     image_reference_collector: ImageReferenceCollector,
+    definition_collector: DefinitionCollector,
     emitter: Emitter,
 }
 
 impl Parser {
     pub fn new(filedata: &str, base_path: Option<PathBuf>) -> Self {
-        match YamlFrontMatter::parse::<Metadata>(filedata) {
-            Ok(document) => Self {
-                metadata: Some(document.metadata),
-                content: document.content,
-                emitter: Emitter::new(base_path.clone()),
-                ..Default::default()
-            },
-            Err(_) => Self {
-                metadata: None,
-                content: String::from(filedata),
-                emitter: Emitter::new(base_path.clone()),
-                ..Default::default()
-            },
+        let mut parser = Self {
+            content: String::from(filedata),
+            emitter: Emitter::new(base_path.clone()),
+            ..Default::default()
+        };
+
+        // Front matter must open the file; a later `---` is a thematic break
+        let has_front_matter = filedata
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .is_some_and(|line| line.trim() == "---");
+        if has_front_matter {
+            match YamlFrontMatter::parse::<IgnoredAny>(filedata) {
+                Ok(document) => {
+                    parser.content = document.content;
+                    // The document fields and the options are read separately
+                    // so that a bad value only loses its own part
+                    match YamlFrontMatter::parse::<Metadata>(filedata) {
+                        Ok(document) => parser.metadata = Some(document.metadata),
+                        Err(e) => warn!("Ignoring invalid front matter fields: {}", e),
+                    }
+                    match YamlFrontMatter::parse::<Options>(filedata) {
+                        Ok(document) => parser.options = document.metadata,
+                        Err(e) => warn!("Ignoring invalid front matter options: {}", e),
+                    }
+                }
+                Err(e) => warn!("Failed to parse front matter: {}", e),
+            }
         }
+
+        parser.set_options(Options::default());
+        parser
+    }
+
+    // Apply command line options, which take precedence over the front matter
+    pub fn set_options(&mut self, options: Options) {
+        self.emitter.set_options(options.or(self.options.clone()));
     }
 
     // Main function to parse markdown and create a DOCX document
@@ -57,14 +85,19 @@ impl Parser {
             trace!("Content: {}", self.content);
 
             // Multi-pass parsing
-            // Pass 1: Collect image references
+            // Pass 1: Collect image references and link definitions
             info!("Pass 1: ImageReferenceCollector");
             self.image_reference_collector.process_node(&ast, ());
+            info!("Pass 1: DefinitionCollector");
+            self.definition_collector.process_node(&ast, ());
+            self.emitter
+                .set_definitions(std::mem::take(&mut self.definition_collector));
             // Initialize numbering for lists
             self.emitter
                 .set_reference(std::mem::take(&mut self.image_reference_collector));
             info!("Image reference collector:");
             docx = self.emitter.initialize_numbering(docx);
+            docx = self.emitter.initialize_styles(docx);
 
             // Add title and author information
             docx = self.emitter.add_document_metadata(&self.metadata, docx);
@@ -78,4 +111,9 @@ impl Parser {
 
         docx
     }
+
+    // Build the DOCX package parts, applying any edits docx-rs cannot express
+    pub fn build(&self, docx: Docx) -> XMLDocx {
+        self.emitter.post_processor().process(docx.build())
+    }
 }
@@ -0,0 +1,309 @@
+use docx_rs::XMLDocx;
+use log::debug;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Content control aliases marking checkboxes, replaced by `w14:checkbox` properties
+pub const CHECKBOX_CHECKED_ALIAS: &str = "md2docx-checkbox-checked";
+pub const CHECKBOX_UNCHECKED_ALIAS: &str = "md2docx-checkbox-unchecked";
+
+/// Applies edits to the generated XML for features that docx-rs cannot express
+#[derive(Default, Debug, Clone)]
+pub struct PostProcessor {
+    hyperlink_tooltips: Vec<(String, String)>,
+}
+
+impl PostProcessor {
+    /// Register a screen tip for the hyperlink with relationship id `rid`
+    pub fn add_hyperlink_tooltip(&mut self, rid: &str, tooltip: &str) {
+        self.hyperlink_tooltips
+            .push((rid.to_string(), tooltip.to_string()));
+    }
+
+    pub fn process(&self, mut xml: XMLDocx) -> XMLDocx {
+        let mut document = String::from_utf8_lossy(&xml.document).into_owned();
+
+        for (rid, tooltip) in &self.hyperlink_tooltips {
+            debug!("Adding tooltip to hyperlink {}: {}", rid, tooltip);
+            document = document.replace(
+                &format!(r#"<w:hyperlink r:id="{}""#, rid),
+                &format!(
+                    r#"<w:hyperlink r:id="{}" w:tooltip="{}""#,
+                    rid,
+                    escape_attribute(tooltip)
+                ),
+            );
+        }
+
+        for (alias, checked) in [
+            (CHECKBOX_CHECKED_ALIAS, true),
+            (CHECKBOX_UNCHECKED_ALIAS, false),
+        ] {
+            document = document.replace(
+                &format!(r#"<w:alias w:val="{}" />"#, alias),
+                &checkbox_property(checked),
+            );
+        }
+
+        document = add_table_headers(&document);
+
+        xml.document = document.into_bytes();
+
+        if !xml.footnotes.is_empty() {
+            let footnotes = String::from_utf8_lossy(&xml.footnotes);
+            xml.footnotes = add_footnote_marks(&footnotes).into_bytes();
+        }
+        xml
+    }
+}
+
+static FOOTNOTE_START_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)(<w:footnote w:id="\d+"[^>]*>\s*<w:p\b[^>]*>\s*<w:pPr>.*?</w:pPr>)"#).unwrap()
+});
+
+/// Starts each footnote with its auto-numbered reference mark, which docx-rs
+/// does not emit
+fn add_footnote_marks(footnotes: &str) -> String {
+    FOOTNOTE_START_REGEX
+        .replace_all(
+            footnotes,
+            r#"$1<w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteRef /></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r>"#,
+        )
+        .into_owned()
+}
+
+static TABLE_ROW_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<w:tr\b[^>]*>"#).unwrap());
+
+/// Marks the first row of each table as a header row, repeated at the top of
+/// each page the table spans, which docx-rs cannot express
+fn add_table_headers(document: &str) -> String {
+    let mut result = String::with_capacity(document.len());
+    let mut rest = document;
+    while let Some(start) = rest.find("<w:tbl>") {
+        let (before, table) = rest.split_at(start);
+        let end = table
+            .find("</w:tbl>")
+            .map_or(table.len(), |end| end + "</w:tbl>".len());
+        let (table, after) = table.split_at(end);
+        result.push_str(before);
+        result.push_str(&add_table_header(table));
+        rest = after;
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Marks the first row of a single table, if it has rows, as its header
+fn add_table_header(table: &str) -> String {
+    let Some(row) = TABLE_ROW_REGEX.find(table) else {
+        return table.to_string();
+    };
+    let row_end = table[row.end()..]
+        .find("</w:tr>")
+        .map_or(table.len(), |end| row.end() + end);
+    let first_row = &table[row.end()..row_end];
+    if let Some(properties) = first_row.find("<w:trPr>") {
+        let at = row.end() + properties + "<w:trPr>".len();
+        format!("{}<w:tblHeader />{}", &table[..at], &table[at..])
+    } else if let Some(properties) = first_row.find("<w:trPr />") {
+        let at = row.end() + properties;
+        format!(
+            "{}<w:trPr><w:tblHeader /></w:trPr>{}",
+            &table[..at],
+            &table[at + "<w:trPr />".len()..]
+        )
+    } else {
+        format!(
+            "{}<w:trPr><w:tblHeader /></w:trPr>{}",
+            &table[..row.end()],
+            &table[row.end()..]
+        )
+    }
+}
+
+/// The content control property that makes a content control a checkbox
+fn checkbox_property(checked: bool) -> String {
+    format!(
+        concat!(
+            r#"<w14:checkbox><w14:checked w14:val="{}" />"#,
+            r#"<w14:checkedState w14:val="2612" w14:font="MS Gothic" />"#,
+            r#"<w14:uncheckedState w14:val="2610" w14:font="MS Gothic" /></w14:checkbox>"#
+        ),
+        if checked { 1 } else { 0 }
+    )
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use docx_rs::{
+        Docx, Footnote, Hyperlink, HyperlinkData, HyperlinkType, Paragraph, Run, StructuredDataTag,
+        Table, TableCell, TableRow,
+    };
+
+    const FOOTNOTE_MARK: &str =
+        r#"<w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteRef /></w:r>"#;
+
+    fn paragraph(text: &str) -> Paragraph {
+        Paragraph::new()
+            .style("FootnoteText")
+            .add_run(Run::new().add_text(text))
+    }
+
+    fn footnotes(footnotes: Vec<Footnote>) -> String {
+        let body = footnotes
+            .into_iter()
+            .fold(Paragraph::new(), |paragraph, footnote| {
+                paragraph.add_run(Run::new().add_footnote_reference(footnote))
+            });
+        String::from_utf8(Docx::new().add_paragraph(body).build().footnotes).unwrap()
+    }
+
+    // A table like the emitter's, whose header row cannot split
+    fn table(rows: usize) -> Table {
+        Table::new(
+            (0..rows)
+                .map(|i| {
+                    let row = TableRow::new(vec![
+                        TableCell::new()
+                            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("cell"))),
+                    ]);
+                    if i == 0 { row.cant_split() } else { row }
+                })
+                .collect(),
+        )
+    }
+
+    // The rows of each table in the document
+    fn rows(xml: &str) -> Vec<Vec<&str>> {
+        xml.split("<w:tbl>")
+            .skip(1)
+            .map(|table| table.split("</w:tr>").collect())
+            .collect()
+    }
+
+    fn document(docx: Docx) -> String {
+        String::from_utf8(docx.build().document).unwrap()
+    }
+
+    fn link(url: &str) -> (Hyperlink, String) {
+        let link = Hyperlink::new(url, HyperlinkType::External).add_run(Run::new().add_text(url));
+        let HyperlinkData::External { rid, .. } = &link.link else {
+            unreachable!()
+        };
+        let rid = rid.clone();
+        (link, rid)
+    }
+
+    #[test]
+    fn tooltips_are_escaped_and_only_added_to_their_hyperlink() {
+        let (titled, rid) = link("https://example.com/titled");
+        let (plain, plain_rid) = link("https://example.com/plain");
+        let docx =
+            Docx::new().add_paragraph(Paragraph::new().add_hyperlink(titled).add_hyperlink(plain));
+        let mut post_processor = PostProcessor::default();
+        post_processor.add_hyperlink_tooltip(&rid, r#"Say "hi" <now> & later"#);
+        let xml = String::from_utf8(post_processor.process(docx.build()).document).unwrap();
+        assert!(xml.contains(&format!(
+            r#"<w:hyperlink r:id="{}" w:tooltip="Say &quot;hi&quot; &lt;now&gt; &amp; later""#,
+            rid
+        )));
+        assert!(xml.contains(&format!(r#"<w:hyperlink r:id="{}" "#, plain_rid)));
+        assert_eq!(xml.matches("w:tooltip=").count(), 1);
+    }
+
+    #[test]
+    fn checkbox_aliases_become_checkbox_controls() {
+        let docx = [CHECKBOX_CHECKED_ALIAS, CHECKBOX_UNCHECKED_ALIAS]
+            .into_iter()
+            .fold(Docx::new(), |docx, alias| {
+                docx.add_paragraph(
+                    Paragraph::new().add_structured_data_tag(
+                        StructuredDataTag::new()
+                            .alias(alias)
+                            .add_run(Run::new().add_text("x")),
+                    ),
+                )
+            });
+        let xml =
+            String::from_utf8(PostProcessor::default().process(docx.build()).document).unwrap();
+        assert!(!xml.contains("md2docx-checkbox"));
+        let checked = xml.find(r#"<w14:checked w14:val="1" />"#).unwrap();
+        let unchecked = xml.find(r#"<w14:checked w14:val="0" />"#).unwrap();
+        assert!(checked < unchecked);
+        assert_eq!(xml.matches("<w14:checkbox>").count(), 2);
+    }
+
+    #[test]
+    fn footnote_marks_start_each_footnote() {
+        let xml = footnotes(vec![
+            Footnote::new().add_content(paragraph("First")),
+            Footnote::new()
+                .add_content(paragraph("Second"))
+                .add_content(paragraph("Continued")),
+        ]);
+        let marked = add_footnote_marks(&xml);
+        assert_eq!(marked.matches(FOOTNOTE_MARK).count(), 2);
+        for footnote in marked.split("<w:footnote ").skip(1) {
+            let paragraphs: Vec<_> = footnote.split("</w:p>").collect();
+            assert!(paragraphs[0].contains(&format!(
+                r#"</w:pPr>{}<w:r><w:t xml:space="preserve"> </w:t></w:r>"#,
+                FOOTNOTE_MARK
+            )));
+            assert!(paragraphs[1..].iter().all(|p| !p.contains(FOOTNOTE_MARK)));
+        }
+    }
+
+    #[test]
+    fn footnote_marks_leave_documents_without_footnotes_alone() {
+        let xml = footnotes(vec![]);
+        assert_eq!(add_footnote_marks(&xml), xml);
+    }
+
+    #[test]
+    fn table_headers_mark_the_first_row_of_each_table() {
+        let xml = document(Docx::new().add_table(table(3)).add_table(table(2)));
+        let marked = add_table_headers(&xml);
+        assert_eq!(marked.matches("<w:tblHeader />").count(), 2);
+        for rows in rows(&marked) {
+            assert!(rows[0].contains("<w:trPr><w:tblHeader /><w:cantSplit /></w:trPr>"));
+            assert!(rows[1..].iter().all(|row| !row.contains("<w:tblHeader />")));
+        }
+    }
+
+    #[test]
+    fn table_headers_skip_tables_without_rows() {
+        let xml = document(Docx::new().add_table(table(0)).add_table(table(2)));
+        let marked = add_table_headers(&xml);
+        let tables = rows(&marked);
+        assert!(!tables[0][0].contains("<w:tblHeader />"));
+        assert!(tables[1][0].contains("<w:tblHeader />"));
+        assert_eq!(marked.matches("<w:tblHeader />").count(), 1);
+    }
+
+    #[test]
+    fn table_headers_fill_empty_row_properties() {
+        let xml = "<w:tbl><w:tr><w:trPr /><w:tc /></w:tr><w:tr><w:trPr><w:cantSplit /></w:trPr></w:tr></w:tbl>";
+        assert_eq!(
+            add_table_headers(xml),
+            "<w:tbl><w:tr><w:trPr><w:tblHeader /></w:trPr><w:tc /></w:tr><w:tr><w:trPr><w:cantSplit /></w:trPr></w:tr></w:tbl>"
+        );
+    }
+
+    #[test]
+    fn table_headers_add_row_properties_when_missing() {
+        let xml = "<w:tbl><w:tblPr /><w:tr><w:tc /></w:tr></w:tbl>";
+        assert_eq!(
+            add_table_headers(xml),
+            "<w:tbl><w:tblPr /><w:tr><w:trPr><w:tblHeader /></w:trPr><w:tc /></w:tr></w:tbl>"
+        );
+    }
+}